@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use cooplan_lapin_wrapper::amqp_wrapper::AmqpWrapper;
+use cooplan_lapin_wrapper::config::amqp_connect_config::AmqpConnectConfig;
+use lapin::Channel;
+use tokio::sync::Mutex;
+use tokio::time::Duration;
+
+use crate::config::reconnection_config::ReconnectionConfig;
+use crate::error::{Error, ErrorKind};
+
+/// Provides the channels of every component, replacing the connections once
+/// they have been closed.
+pub struct ConnectionSupervisor {
+    amqp_wrapper: Mutex<AmqpWrapper>,
+    connect_config: AmqpConnectConfig,
+    reconnection: ReconnectionConfig,
+}
+
+impl ConnectionSupervisor {
+    pub fn try_new(
+        connect_config: AmqpConnectConfig,
+        reconnection: ReconnectionConfig,
+    ) -> Result<ConnectionSupervisor, Error> {
+        let amqp_wrapper = try_new_amqp_wrapper(&connect_config)?;
+
+        Ok(ConnectionSupervisor {
+            amqp_wrapper: Mutex::new(amqp_wrapper),
+            connect_config,
+            reconnection,
+        })
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        Backoff::new(self.reconnection)
+    }
+
+    /// Whenever no channel can be created, the connections are dropped so the next
+    /// attempt connects again, since the wrapper keeps reusing its latest connection.
+    pub async fn try_get_channel(&self) -> Result<Arc<Channel>, Error> {
+        let mut amqp_wrapper = self.amqp_wrapper.lock().await;
+
+        match amqp_wrapper.try_get_channel().await {
+            Ok(channel) => Ok(channel),
+            Err(error) => {
+                *amqp_wrapper = try_new_amqp_wrapper(&self.connect_config)?;
+
+                Err(Error::new(
+                    ErrorKind::AmqpFailure,
+                    format!("failed to get channel: {}", error),
+                ))
+            }
+        }
+    }
+}
+
+/// Exponential backoff between reconnection attempts. Every delay is a random one between
+/// half and the whole of the current backoff, so components losing their connection at
+/// once do not reconnect in lockstep.
+pub struct Backoff {
+    next_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Backoff {
+    pub fn new(reconnection: ReconnectionConfig) -> Backoff {
+        Backoff {
+            next_backoff: Duration::from_millis(reconnection.initial_backoff_in_milliseconds()),
+            max_backoff: Duration::from_millis(reconnection.max_backoff_in_milliseconds()),
+        }
+    }
+
+    pub async fn wait(&mut self) {
+        tokio::time::sleep(self.next_delay()).await;
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let backoff = self.next_backoff;
+        self.next_backoff = std::cmp::min(backoff * 2, self.max_backoff);
+
+        let half = backoff / 2;
+
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+fn try_new_amqp_wrapper(connect_config: &AmqpConnectConfig) -> Result<AmqpWrapper, Error> {
+    let connect_config = AmqpConnectConfig::new(
+        connect_config.uri().to_string(),
+        connect_config.cloned_options(),
+        connect_config.cloned_owned_tls_config(),
+    );
+
+    match AmqpWrapper::try_new(connect_config) {
+        Ok(amqp_wrapper) => Ok(amqp_wrapper),
+        Err(error) => Err(Error::new(
+            ErrorKind::InternalFailure,
+            format!("failed to initialize amqp wrapper: {}", error),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_backoff_up_to_max() {
+        let mut backoff = Backoff::new(ReconnectionConfig::new(100, 1000));
+
+        for expected_backoff in [100, 200, 400, 800, 1000, 1000] {
+            let delay = backoff.next_delay();
+
+            assert!(delay >= Duration::from_millis(expected_backoff / 2), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(expected_backoff), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn spreads_delays_of_concurrent_backoffs() {
+        let delays: Vec<Duration> = (0..16)
+            .map(|_| Backoff::new(ReconnectionConfig::new(1000, 1000)).next_delay())
+            .collect();
+
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+}
@@ -1,61 +1,163 @@
 use std::sync::Arc;
-use cooplan_lapin_wrapper::amqp_wrapper::AmqpWrapper;
 
+use tokio::time::Duration;
+
+use crate::api::connection_supervisor::ConnectionSupervisor;
 use crate::api::initialization_package::InitializationPackage;
 use crate::api::input::amqp_request_dispatch::AmqpRequestDispatch;
-use crate::api::input::authorizer::try_generate_authorizer;
-use crate::error::{Error, ErrorKind};
+use crate::api::input::authorizer::Authorizer;
+use crate::api::input::jwt_authorizer::try_generate_jwt_authorizer;
+use crate::api::input::revocation_consumer::RevocationConsumer;
+use crate::api::shutdown::ApiHandle;
+use crate::config::permission_mappings::PermissionMappings;
+use crate::error::Error;
 
 use super::output::amqp_output_router::AmqpOutputRouter;
 
+const ABORTED_INITIALIZATION_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns once every queue has been declared and consumed, failing on the first
+/// declaration that fails. The returned handle stops the api, which otherwise runs as
+/// long as the program does, and watches for fatal errors while running.
 pub async fn initialize<LogicRequestType: Send + 'static>(
     package: InitializationPackage<LogicRequestType>,
-) -> Result<(), Error> {
+) -> Result<ApiHandle, Error> {
     let logic_request_sender = package.logic_request_sender();
+    let middlewares = package.middlewares;
 
     let api = package.api;
 
     let input_registration = package.input_registration;
     let input_elements = input_registration(&api)?;
 
+    let state_tracker_client = package.state_tracker_client;
+
     let config = package.config;
-    let authorizer = Arc::new(try_generate_authorizer(config.openid_connect).await?);
 
-    let connect_config = config.amqp_connect_config;
-    let mut amqp_wrapper =
-        match AmqpWrapper::try_new(connect_config) {
-            Ok(amqp_wrapper) => amqp_wrapper,
-            Err(error) => return Err(Error::new(ErrorKind::InternalFailure, format!("failed to initialize amqp wrapper: {}", error))),
+    let revocation_list = package.revocation_list;
+
+    let authorizer: Arc<dyn Authorizer> = match package.authorizer {
+        Some(authorizer) => authorizer,
+        None => {
+            let jwt_authorizer: Arc<dyn Authorizer> = Arc::new(
+                try_generate_jwt_authorizer(
+                    config.openid_connect,
+                    PermissionMappings::new(config.roles, config.scopes),
+                    revocation_list.clone(),
+                    state_tracker_client.clone(),
+                )
+                .await?,
+            );
+
+            match package.authorizer_registration {
+                Some(authorizer_registration) => authorizer_registration(jwt_authorizer),
+                None => jwt_authorizer,
+            }
+        }
+    };
+
+    let output_registration = package.output_registration;
+    let output_elements = output_registration(&api, state_tracker_client.clone())?;
+
+    let connection_supervisor = Arc::new(ConnectionSupervisor::try_new(
+        config.amqp_connect_config,
+        config.reconnection,
+    )?);
+
+    let mut api_handle = ApiHandle::new(state_tracker_client.clone());
+
+    let authorizer_signal = api_handle.input_signal();
+    let running_authorizer = authorizer.clone();
+    api_handle.spawn_input("authorizer".to_string(), async move {
+        running_authorizer.run(authorizer_signal).await
+    });
+
+    if let Some(revocation) = config.revocation {
+        let channel = match connection_supervisor.try_get_channel().await {
+            Ok(channel) => channel,
+            Err(error) => return Err(abort(api_handle, error).await),
         };
 
-    let state_tracker_client = package.state_tracker_client;
+        let revocation_consumer =
+            RevocationConsumer::new(channel, connection_supervisor.clone(), revocation, revocation_list, state_tracker_client.clone());
+
+        if let Err(error) = try_spawn_revocation_consumer(&mut api_handle, revocation_consumer).await {
+            return Err(abort(api_handle, error).await);
+        }
+    }
 
     for input_element in input_elements {
-        let channel = match amqp_wrapper.try_get_channel().await {
+        let channel = match connection_supervisor.try_get_channel().await {
             Ok(channel) => channel,
-            Err(error) => return Err(Error::new(ErrorKind::InternalFailure, format!("failed to get channel: {}", error))),
+            Err(error) => return Err(abort(api_handle, error).await),
         };
 
         let dispatch =
-            AmqpRequestDispatch::new(channel, input_element, authorizer.clone(), logic_request_sender.clone(), state_tracker_client.clone());
+            AmqpRequestDispatch::new(channel, connection_supervisor.clone(), input_element, authorizer.clone(), middlewares.clone(), logic_request_sender.clone(), state_tracker_client.clone());
 
-        tokio::spawn(dispatch.run());
+        if let Err(error) = try_spawn_dispatch(&mut api_handle, dispatch).await {
+            return Err(abort(api_handle, error).await);
+        }
     }
 
-    let output_registration = package.output_registration;
-    let output_elements = output_registration(&api, state_tracker_client.clone())?;
-    let output_channel = match amqp_wrapper.try_get_channel().await {
+    let output_channel = match connection_supervisor.try_get_channel().await {
         Ok(channel) => channel,
-        Err(error) => return Err(Error::new(ErrorKind::InternalFailure, format!("failed to get channel: {}", error))),
+        Err(error) => return Err(abort(api_handle, error).await),
     };
 
-    let output_router = AmqpOutputRouter::new(
+    let output_router = match AmqpOutputRouter::try_new(
         output_channel,
+        connection_supervisor,
         output_elements,
         package.output_receiver,
+        &mut api_handle,
+    )
+    .await
+    {
+        Ok(output_router) => output_router,
+        Err(error) => return Err(abort(api_handle, error).await),
+    };
+
+    let output_signal = api_handle.output_signal();
+    api_handle.spawn_output("output_router".to_string(), output_router.run(output_signal));
+
+    Ok(api_handle)
+}
+
+async fn try_spawn_revocation_consumer(
+    api_handle: &mut ApiHandle,
+    mut revocation_consumer: RevocationConsumer,
+) -> Result<(), Error> {
+    revocation_consumer.try_declare().await?;
+
+    let input_signal = api_handle.input_signal();
+    api_handle.spawn_input(
+        "revocation_consumer".to_string(),
+        revocation_consumer.run(input_signal),
     );
 
-    tokio::spawn(output_router.run());
+    Ok(())
+}
+
+async fn try_spawn_dispatch<LogicRequestType: Send + 'static>(
+    api_handle: &mut ApiHandle,
+    mut dispatch: AmqpRequestDispatch<LogicRequestType>,
+) -> Result<(), Error> {
+    dispatch.try_declare().await?;
+
+    let input_signal = api_handle.input_signal();
+    api_handle.spawn_input(dispatch.name().to_string(), dispatch.run(input_signal));
 
     Ok(())
 }
+
+/// Stops the components already running whenever the initialization fails.
+async fn abort(api_handle: ApiHandle, error: Error) -> Error {
+    log::error!("failed to initialize api: {}", error);
+
+    if let Err(shutdown_error) = api_handle.shutdown(ABORTED_INITIALIZATION_SHUTDOWN_TIMEOUT).await {
+        log::warn!("failed to shut down components: {}", shutdown_error);
+    }
+
+    error
+}
@@ -1,4 +1,9 @@
+use std::sync::Arc;
+
+use crate::api::input::authorizer::Authorizer;
 use crate::api::input::input_element::InputElement;
+use crate::api::input::middleware::Middleware;
+use crate::api::input::revocation_list::RevocationList;
 use crate::error::Error;
 use async_channel::Sender;
 use cooplan_lapin_wrapper::config::api::Api;
@@ -10,6 +15,10 @@ use super::output::amqp_output_element::AmqpOutputElement;
 
 pub type InputRegistration<LogicRequestType> =
     Box<dyn FnOnce(&Api) -> Result<Vec<InputElement<LogicRequestType>>, Error> + Send + Sync>;
+/// Receives the default jwt authorizer and returns the authorizer to use, e.g. a chain
+/// containing it.
+pub type AuthorizerRegistration =
+    Box<dyn FnOnce(Arc<dyn Authorizer>) -> Arc<dyn Authorizer> + Send + Sync>;
 pub type OutputRegistration =
     Box<dyn FnOnce(&Api, StateTrackerClient) -> Result<Vec<AmqpOutputElement>, Error> + Send + Sync>;
 
@@ -20,7 +29,17 @@ pub struct InitializationPackage<LogicRequestType> {
     pub output_registration: OutputRegistration,
     pub api: Api,
     pub config: Config,
-    pub state_tracker_client: StateTrackerClient
+    pub state_tracker_client: StateTrackerClient,
+    /// Replaces the default jwt authorizer whenever set.
+    pub authorizer: Option<Arc<dyn Authorizer>>,
+    /// Ignored whenever an authorizer is set.
+    pub authorizer_registration: Option<AuthorizerRegistration>,
+    /// Filled by the revocation consumer, for the default jwt authorizer as well as
+    /// for any jwt authorizer generated by the caller.
+    pub revocation_list: Arc<RevocationList>,
+    /// Run first for the requests of every input element, before the built-in middlewares
+    /// and the element's ones.
+    pub middlewares: Vec<Arc<dyn Middleware<LogicRequestType>>>,
 }
 
 impl<LogicRequestType> InitializationPackage<LogicRequestType> {
@@ -33,6 +52,11 @@ impl<LogicRequestType> InitializationPackage<LogicRequestType> {
         config: Config,
         state_tracker_client: StateTrackerClient
     ) -> InitializationPackage<LogicRequestType> {
+        let revocation_ttl = match &config.revocation {
+            Some(revocation) => revocation.default_ttl_in_seconds(),
+            None => 0,
+        };
+
         InitializationPackage {
             logic_request_sender,
             input_registration,
@@ -40,10 +64,30 @@ impl<LogicRequestType> InitializationPackage<LogicRequestType> {
             output_registration,
             api,
             config,
-            state_tracker_client
+            state_tracker_client,
+            authorizer: None,
+            authorizer_registration: None,
+            revocation_list: Arc::new(RevocationList::new(revocation_ttl)),
+            middlewares: Vec::new(),
         }
     }
 
+    pub fn set_authorizer(&mut self, authorizer: Arc<dyn Authorizer>) {
+        self.authorizer = Some(authorizer);
+    }
+
+    pub fn set_authorizer_registration(&mut self, authorizer_registration: AuthorizerRegistration) {
+        self.authorizer_registration = Some(authorizer_registration);
+    }
+
+    pub fn revocation_list(&self) -> Arc<RevocationList> {
+        self.revocation_list.clone()
+    }
+
+    pub fn add_middleware(&mut self, middleware: Arc<dyn Middleware<LogicRequestType>>) {
+        self.middlewares.push(middleware);
+    }
+
     pub fn logic_request_sender(&self) -> Sender<LogicRequestType> {
         self.logic_request_sender.clone()
     }
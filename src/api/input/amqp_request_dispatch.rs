@@ -1,6 +1,6 @@
-use std::sync::atomic::AtomicU16;
 use std::sync::Arc;
 
+use crate::api::connection_supervisor::ConnectionSupervisor;
 use crate::api::input::amqp_request_replier;
 use crate::api::input::authorizer::Authorizer;
 use async_channel::Sender;
@@ -9,50 +9,420 @@ use cooplan_state_tracker::state::State;
 use cooplan_state_tracker::state_tracker_client::StateTrackerClient;
 use futures_util::TryStreamExt;
 use lapin::message::Delivery;
+use lapin::options::{
+    BasicCancelOptions, BasicRejectOptions, ConfirmSelectOptions, QueueDeclareOptions,
+};
+use lapin::types::{AMQPValue, FieldTable, LongString, ShortString};
 use lapin::{Channel, Consumer};
-use serde_json::{Map, Value};
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
 use uuid::Uuid;
 
+use crate::api::input::deadline;
+use crate::api::input::disposition::{DeliveryDisposer, Failure, Outcome};
 use crate::api::input::input_element::InputElement;
+use crate::api::input::middleware::authorization_middleware::AuthorizationMiddleware;
+use crate::api::input::middleware::deduplication_middleware::DeduplicationMiddleware;
+use crate::api::input::middleware::expiry_middleware::ExpiryMiddleware;
+use crate::api::input::middleware::parsing_middleware::ParsingMiddleware;
+use crate::api::input::middleware::policy_middleware::PolicyMiddleware;
+use crate::api::input::middleware::sanitization_middleware::SanitizationMiddleware;
+use crate::api::input::middleware::timeout_middleware::TimeoutMiddleware;
+use crate::api::input::middleware::{Middleware, Pipeline};
+use crate::api::input::retry_policy;
 use crate::api::input::request::Request;
-use crate::api::input::sanitizer::sanitize;
+use crate::api::shutdown;
+use crate::api::shutdown::ShutdownSignal;
 use crate::error::{Error, ErrorKind};
 
-use super::amqp_request_replier::AmqpRequestReplier;
+const DEAD_LETTER_EXCHANGE_ARGUMENT: &str = "x-dead-letter-exchange";
+const DEAD_LETTER_ROUTING_KEY_ARGUMENT: &str = "x-dead-letter-routing-key";
 
 pub struct AmqpRequestDispatch<LogicRequestType> {
     channel: Arc<Channel>,
+    connection_supervisor: Arc<ConnectionSupervisor>,
+    consumer: Option<Consumer>,
     element: InputElement<LogicRequestType>,
-    authorizer: Arc<Authorizer>,
+    pipeline: Arc<Pipeline<LogicRequestType>>,
     logic_request_sender: Sender<LogicRequestType>,
-    current_concurrent_requests: Arc<AtomicU16>,
     state_tracker_client: StateTrackerClient,
 }
 
 impl<LogicRequestType: Send + 'static> AmqpRequestDispatch<LogicRequestType> {
+    /// The global middlewares run first, so they see every request, even those stopped by
+    /// the built-in parsing, expiry, sanitization, authorization and policies, which run
+    /// before the element's middlewares. Deduplication and timeout wrap the request handler.
     pub fn new(
         channel: Arc<Channel>,
+        connection_supervisor: Arc<ConnectionSupervisor>,
         element: InputElement<LogicRequestType>,
-        authorizer: Arc<Authorizer>,
+        authorizer: Arc<dyn Authorizer>,
+        global_middlewares: Vec<Arc<dyn Middleware<LogicRequestType>>>,
         logic_request_sender: Sender<LogicRequestType>,
         mut state_tracker_client: StateTrackerClient,
     ) -> AmqpRequestDispatch<LogicRequestType> {
         state_tracker_client.set_id(element.name().to_string());
 
+        let mut middlewares = global_middlewares;
+        middlewares.extend([
+            Arc::new(ParsingMiddleware) as Arc<dyn Middleware<LogicRequestType>>,
+            Arc::new(ExpiryMiddleware::new(
+                element
+                    .actions()
+                    .iter()
+                    .filter_map(|action| Some((*action, element.timeout(action)?)))
+                    .collect(),
+            )),
+            Arc::new(SanitizationMiddleware::new(element.actions())),
+            Arc::new(AuthorizationMiddleware::new(
+                authorizer,
+                element.action_accesses().clone(),
+                element.permission_requirements().clone(),
+                element.tenant_isolated(),
+            )),
+            Arc::new(PolicyMiddleware::new(element.policies().to_vec())),
+        ]);
+        middlewares.extend(element.middlewares().iter().cloned());
+
+        if let Some(idempotency_store) = element.idempotency_store() {
+            middlewares.push(Arc::new(DeduplicationMiddleware::new(
+                element.name().to_string(),
+                idempotency_store,
+            )));
+        }
+
+        middlewares.push(Arc::new(TimeoutMiddleware));
+
+        let pipeline = Arc::new(Pipeline::new(middlewares, element.request_handler()));
+
         AmqpRequestDispatch {
             channel,
+            connection_supervisor,
+            consumer: None,
             element,
-            authorizer,
+            pipeline,
             logic_request_sender,
-            current_concurrent_requests: Arc::new(AtomicU16::new(0)),
             state_tracker_client
         }
     }
 
+    pub fn name(&self) -> &str {
+        self.element.name()
+    }
+
     /// Blocks thread as long as the program is running.
-    /// Deliveries are received, sanitized and authorized before being moved into a
-    /// new task where the request will be handled.
-    pub async fn run(self) -> Result<(), Error> {
+    /// Deliveries are moved into a new task where they go through the element's pipeline,
+    /// starting with their parsing, after which they are settled and replied.
+    /// No delivery is received while 'max_concurrent_requests' requests are being handled.
+    /// Returns once the shutdown has been requested and the in-flight requests handled.
+    /// Whenever the consumer stops, the queue is declared and consumed again, reconnecting
+    /// if the channel has been closed.
+    pub async fn run(mut self, mut shutdown_signal: ShutdownSignal) -> Result<(), Error> {
+        let mut consumer = match self.consumer.take() {
+            Some(consumer) => consumer,
+            None => self.try_set_up().await?,
+        };
+
+        let max_concurrent_requests = self.element.config().max_concurrent_requests();
+
+        let disposer = Arc::new(DeliveryDisposer::new(
+            self.element.name().to_string(),
+            self.element.dispositions().clone(),
+            *self.element.config().queue_consumer().reject(),
+            self.element.retry_policy().cloned(),
+            self.element.postponement_delay(),
+        ));
+        let acknowledge_options = *self.element.config().queue_consumer().acknowledge();
+        let concurrent_requests = Arc::new(Semaphore::new(max_concurrent_requests as usize));
+
+        let deadline = loop {
+            // Released once the request has been handled, or dropped along with a failed delivery.
+            let permit = tokio::select! {
+                permit = concurrent_requests.clone().acquire_owned() => match permit {
+                    Ok(permit) => permit,
+                    Err(error) => {
+                        return Err(Error::new(
+                            ErrorKind::InternalFailure,
+                            format!("failed to acquire concurrent request permit: {}", error),
+                        ));
+                    }
+                },
+                deadline = shutdown_signal.requested() => break deadline,
+            };
+
+            let state_tracker_client = self.state_tracker_client.clone();
+
+            let next_delivery = tokio::select! {
+                next_delivery = consumer.try_next() => next_delivery,
+                deadline = shutdown_signal.requested() => break deadline,
+            };
+
+            let delivery = match next_delivery {
+                Ok(Some(delivery)) => delivery,
+                Ok(None) => {
+                    log::warn!("consumer of '{}' has stopped", self.element.name());
+
+                    consumer = tokio::select! {
+                        consumer = self.reconnect() => consumer,
+                        deadline = shutdown_signal.requested() => break deadline,
+                    };
+                    continue;
+                }
+                Err(error) => {
+                    let error_message = format!("consumer got an error: {}", error);
+
+                    match state_tracker_client.send_state(State::Error(error_message.clone())).await {
+                        Ok(_) => (),
+                        Err(error) => log::error!("failed to send error state: {}", error)
+                    }
+
+                    log::warn!("{}", error_message);
+
+                    if !self.channel.status().connected() {
+                        consumer = tokio::select! {
+                            consumer = self.reconnect() => consumer,
+                            deadline = shutdown_signal.requested() => break deadline,
+                        };
+                    }
+
+                    continue;
+                }
+            };
+
+            let channel = self.channel.clone();
+
+            let mut request = Request::from_payload(delivery.data.clone());
+            request.deadline = deadline::delivery_deadline_of(&delivery);
+            request.message_id = delivery
+                .properties
+                .message_id()
+                .as_ref()
+                .map(|message_id| message_id.to_string());
+
+            let element_name = self.element.name().to_string();
+            let pipeline = self.pipeline.clone();
+            let logic_request_sender = self.logic_request_sender.clone();
+            let disposer = disposer.clone();
+
+            tokio::spawn(async move {
+                let result = pipeline.run(request, logic_request_sender).await;
+                let mut state = State::Valid;
+                // Failed requests are only replied once they are not delivered again.
+                let mut outcome = Outcome::Final;
+
+                let result = match result {
+                    Ok(result) => {
+                        match &result {
+                            RequestResult::Ok(_) => {
+                                if let Err(error) = delivery.ack(acknowledge_options).await {
+                                    let error_message =
+                                        format!("failed to acknowledge delivery: {}", error);
+                                    log::error!("{}", error_message);
+
+                                    state = State::Error(error_message)
+                                }
+                            }
+                            RequestResult::Err(error) => {
+                                log::info!("failed to handle request: {}", error);
+
+                                let failure = Failure::new(
+                                    error.kind().into(),
+                                    format!("{:?}", error.kind()),
+                                    error.message().to_string(),
+                                );
+
+                                match disposer.dispose(&channel, &delivery, &failure).await {
+                                    Ok(disposed) => outcome = disposed,
+                                    Err(error) => {
+                                        log::error!("{}", error);
+
+                                        outcome = Outcome::Redelivered;
+                                        state = State::Error(error.message)
+                                    }
+                                }
+                            }
+                        }
+
+                        Some(Ok(result))
+                    }
+                    Err(error) => match error.kind() {
+                        ErrorKind::ExpiredRequest => {
+                            log::info!("dropping request of '{}': {}", element_name, error);
+
+                            if let Err(error) = drop_expired(&delivery).await {
+                                log::error!("{}", error);
+
+                                state = State::Error(error.message)
+                            }
+
+                            None
+                        }
+                        ErrorKind::DuplicateRequest => {
+                            log::info!("postponing request of '{}': {}", element_name, error);
+
+                            if let Err(error) = disposer.postpone(&channel, &delivery).await {
+                                log::error!("{}", error);
+
+                                state = State::Error(error.message)
+                            }
+
+                            None
+                        }
+                        _ => {
+                            log::info!("failed to prepare request: {}", error);
+
+                            match disposer
+                                .dispose(&channel, &delivery, &Failure::from(&error))
+                                .await
+                            {
+                                Ok(disposed) => outcome = disposed,
+                                Err(error) => {
+                                    log::error!("{}", error);
+
+                                    outcome = Outcome::Redelivered;
+                                    state = State::Error(error.message)
+                                }
+                            }
+
+                            Some(Err(error))
+                        }
+                    },
+                };
+
+                match state_tracker_client.send_state(state).await {
+                    Ok(_) => (),
+                    Err(error) => log::warn!("failed to send state: {}", error)
+                }
+
+                if outcome == Outcome::Redelivered {
+                    log::debug!("not replying request of '{}' delivered again", element_name);
+                } else if let Some(result) = result {
+                    if let Some(amqp_request_replier) =
+                        amqp_request_replier::try_generate_replier(&channel, &delivery)
+                    {
+                        let replied = match result {
+                            Ok(result) => amqp_request_replier.reply(result).await,
+                            Err(error) => amqp_request_replier.reply_error(error).await,
+                        };
+
+                        match replied {
+                            Ok(_) => (),
+                            Err(error) => {
+                                log::info!("failed to reply: {}", error);
+                            }
+                        }
+                    }
+                }
+
+                drop(permit);
+            });
+        };
+
+        self.shutdown(consumer, concurrent_requests, max_concurrent_requests, deadline)
+            .await
+    }
+
+    /// Cancels the consumer and waits for the in-flight requests before closing the channel.
+    /// Deliveries prefetched but not yet handled are requeued by the broker.
+    async fn shutdown(
+        &self,
+        consumer: Consumer,
+        concurrent_requests: Arc<Semaphore>,
+        max_concurrent_requests: u16,
+        deadline: Instant,
+    ) -> Result<(), Error> {
+        let connected = self.channel.status().connected();
+
+        if connected {
+            if let Err(error) = self
+                .channel
+                .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+                .await
+            {
+                log::warn!("failed to cancel consumer of '{}': {}", self.element.name(), error);
+            }
+        }
+
+        match tokio::time::timeout_at(
+            deadline,
+            concurrent_requests.acquire_many(max_concurrent_requests as u32),
+        )
+        .await
+        {
+            Ok(_) => (),
+            Err(_) => {
+                return Err(Error::new(
+                    ErrorKind::ShutdownFailure,
+                    format!(
+                        "in-flight requests of '{}' were not handled before the deadline",
+                        self.element.name()
+                    ),
+                ));
+            }
+        }
+
+        if connected {
+            shutdown::close_channel(&self.channel).await?;
+        }
+
+        match self.state_tracker_client.send_state(State::Idle).await {
+            Ok(_) => (),
+            Err(error) => log::warn!("failed to send idle state: {}", error),
+        }
+
+        Ok(())
+    }
+
+    /// Declares and consumes the queue ahead of [AmqpRequestDispatch::run], so that
+    /// misconfigurations are noticed before the dispatch is spawned.
+    pub async fn try_declare(&mut self) -> Result<(), Error> {
+        self.consumer = Some(self.try_set_up().await?);
+
+        Ok(())
+    }
+
+    /// Declares the queue and sets the qos of the channel before consuming the queue.
+    /// Misconfigured elements fail before anything is declared.
+    /// The channel is put in confirm mode, so replies, retries and dead-lettered
+    /// deliveries are only considered published once the broker has confirmed them.
+    async fn try_set_up(&self) -> Result<Consumer, Error> {
+        if self.element.config().max_concurrent_requests() == 0 {
+            return Err(Error::new(
+                ErrorKind::AutoConfigFailure,
+                format!(
+                    "input element '{}' must allow at least one concurrent request",
+                    self.element.name()
+                ),
+            ));
+        }
+
+        if let Some((action, _)) = self
+            .element
+            .permission_requirements()
+            .iter()
+            .find(|(_, requirement)| requirement.is_empty())
+        {
+            return Err(Error::new(
+                ErrorKind::AutoConfigFailure,
+                format!(
+                    "permission requirement of action '{}' of input element '{}' has no permissions",
+                    action,
+                    self.element.name()
+                ),
+            ));
+        }
+
+        if let Err(error) = self
+            .channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+        {
+            return Err(Error::new(
+                ErrorKind::AmqpFailure,
+                format!("failed to enable publisher confirms: {}", error),
+            ));
+        }
+
         let queue = match self
             .channel
             .queue_declare(
@@ -80,7 +450,7 @@ impl<LogicRequestType: Send + 'static> AmqpRequestDispatch<LogicRequestType> {
         match self
             .channel
             .basic_qos(
-                self.element.config().queue_consumer().qos().prefetch_count(),
+                self.prefetch_count(),
                 *self.element.config().queue_consumer().qos().options(),
             )
             .await
@@ -94,125 +464,125 @@ impl<LogicRequestType: Send + 'static> AmqpRequestDispatch<LogicRequestType> {
             }
         }
 
-        let mut consumer = self.try_get_consumer(queue.name().as_str()).await?;
-
-        let reject_options = *self.element.config().queue_consumer().reject();
-        let acknowledge_options = *self.element.config().queue_consumer().acknowledge();
-        let max_concurrent_requests = self.element.config().max_concurrent_requests();
-
-        loop {
-            if self
-                .current_concurrent_requests
-                .load(std::sync::atomic::Ordering::Relaxed)
-                >= max_concurrent_requests
-            {
-                continue;
-            }
-
-            let state_tracker_client = self.state_tracker_client.clone();
-
-            let delivery = match consumer.try_next().await {
-                Ok(optional_delivery) => match optional_delivery {
-                    Some(delivery) => delivery,
-                    None => {
-                        log::info!("consumer got an empty delivery");
-                        continue;
-                    }
-                },
-                Err(error) => {
-                    let error_message = format!("consumer got an error: {}", error);
-
-                    match state_tracker_client.send_state(State::Error(error_message.clone())).await {
-                        Ok(_) => (),
-                        Err(error) => log::error!("failed to send error state: {}", error)
-                    }
-
-                    log::warn!("{}", error_message);
-                    continue;
-                }
-            };
-
-            let channel = self.channel.clone();
+        self.try_declare_delay_queues(queue.name().as_str()).await?;
 
-            let request_replier: Option<AmqpRequestReplier> =
-                amqp_request_replier::try_generate_replier(&channel, &delivery);
+        self.try_get_consumer(queue.name().as_str()).await
+    }
 
-            let request = match self.prepare_request(&delivery).await {
-                Ok(request) => request,
-                Err(error) => {
-                    if let Some(request_replier) = request_replier {
-                        match request_replier
-                            .reply(RequestResult::Err(error.clone().into()))
-                            .await
-                        {
-                            Ok(_) => (),
-                            Err(error) => {
-                                log::warn!("failed to reply: {}", error);
-                            }
-                        }
-                    }
+    /// Every delay queue dead-letters its expired deliveries back into the queue.
+    /// Elements have a delay queue per retry attempt whenever they have a retry policy,
+    /// and a postponement queue whenever they deduplicate requests.
+    async fn try_declare_delay_queues(&self, queue_name: &str) -> Result<(), Error> {
+        let mut delay_queues = Vec::new();
 
-                    log::info!("failed to prepare request: {}", error);
-                    continue;
-                }
-            };
+        if let Some(retry_policy) = self.element.retry_policy() {
+            delay_queues.extend(
+                (1..retry_policy.max_attempts())
+                    .map(|attempt| retry_policy::delay_queue_name(queue_name, attempt)),
+            );
+        }
 
-            let request_handler = self.element.request_handler();
+        if self.element.idempotency_store().is_some() {
+            delay_queues.push(retry_policy::postponement_queue_name(queue_name));
+        }
 
-            let logic_request_sender = self.logic_request_sender.clone();
+        let options = QueueDeclareOptions {
+            durable: self.element.config().queue_consumer().queue().declare().options().durable,
+            ..QueueDeclareOptions::default()
+        };
 
-            self.current_concurrent_requests
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let mut arguments = FieldTable::default();
+        arguments.insert(
+            ShortString::from(DEAD_LETTER_EXCHANGE_ARGUMENT),
+            AMQPValue::LongString(LongString::from("")),
+        );
+        arguments.insert(
+            ShortString::from(DEAD_LETTER_ROUTING_KEY_ARGUMENT),
+            AMQPValue::LongString(LongString::from(queue_name)),
+        );
 
-            let current_concurrent_requests = self.current_concurrent_requests.clone();
+        for delay_queue in delay_queues {
+            if let Err(error) = self
+                .channel
+                .queue_declare(
+                    delay_queue.as_str(),
+                    options,
+                    arguments.clone(),
+                )
+                .await
+            {
+                return Err(Error::new(
+                    ErrorKind::AmqpFailure,
+                    format!("failed to declare delay queue: {}", error),
+                ));
+            }
+        }
 
-            tokio::spawn(async move {
-                let result = request_handler(request, logic_request_sender).await;
-                let mut state = State::Valid;
+        Ok(())
+    }
 
-                match &result {
-                    RequestResult::Ok(_) => {
-                        if let Err(error) = delivery.ack(acknowledge_options).await {
-                            let error_message = format!("failed to acknowledge delivery: {}", error);
-                            log::error!("{}", error_message);
+    /// Retries until the queue is consumed again, using a new channel whenever
+    /// the current one has been closed.
+    async fn reconnect(&mut self) -> Consumer {
+        let mut backoff = self.connection_supervisor.backoff();
 
-                            state = State::Error(error_message)
-                        }
-                    }
-                    RequestResult::Err(error) => {
-                        log::info!("failed to handle request: {}", error);
+        loop {
+            backoff.wait().await;
 
-                        match delivery.reject(reject_options).await {
-                            Ok(_) => (),
-                            Err(error) => {
-                                let error_message = format!("failed to reject delivery: {}", error);
-                                log::error!("{}", error_message);
+            match self.try_reconnect().await {
+                Ok(consumer) => {
+                    log::info!("'{}' has reconnected", self.element.name());
 
-                                state = State::Error(error_message)
-                            }
-                        }
+                    match self.state_tracker_client.send_state(State::Valid).await {
+                        Ok(_) => (),
+                        Err(error) => log::warn!("failed to send valid state: {}", error),
                     }
-                }
 
-                match state_tracker_client.send_state(state).await {
-                    Ok(_) => (),
-                    Err(error) => log::warn!("failed to send state: {}", error)
+                    return consumer;
                 }
+                Err(error) => {
+                    let error_message =
+                        format!("'{}' failed to reconnect: {}", self.element.name(), error);
+                    log::warn!("{}", error_message);
 
-                if let Some(amqp_request_replier) =
-                    amqp_request_replier::try_generate_replier(&channel, &delivery)
-                {
-                    match amqp_request_replier.reply(result).await {
+                    match self
+                        .state_tracker_client
+                        .send_state(State::Error(error_message))
+                        .await
+                    {
                         Ok(_) => (),
-                        Err(error) => {
-                            log::info!("failed to reply: {}", error);
-                        }
+                        Err(error) => log::error!("failed to send error state: {}", error),
                     }
                 }
+            }
+        }
+    }
 
-                current_concurrent_requests.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
-            });
+    async fn try_reconnect(&mut self) -> Result<Consumer, Error> {
+        if !self.channel.status().connected() {
+            self.channel = self.connection_supervisor.try_get_channel().await?;
+        }
+
+        self.try_set_up().await
+    }
+
+    /// The broker never pushes more deliveries than the requests that can be handled
+    /// concurrently, a prefetch count of 0 meaning unlimited.
+    fn prefetch_count(&self) -> u16 {
+        let max_concurrent_requests = self.element.config().max_concurrent_requests();
+        let prefetch_count = self.element.config().queue_consumer().qos().prefetch_count();
+
+        if prefetch_count == 0 || prefetch_count > max_concurrent_requests {
+            log::debug!(
+                "limiting prefetch count of '{}' to {}",
+                self.element.name(),
+                max_concurrent_requests
+            );
+
+            return max_concurrent_requests;
         }
+
+        prefetch_count
     }
 
     async fn try_get_consumer(&self, queue_name: &str) -> Result<Consumer, Error> {
@@ -243,74 +613,470 @@ impl<LogicRequestType: Send + 'static> AmqpRequestDispatch<LogicRequestType> {
 
         Ok(consumer)
     }
+}
 
-    async fn prepare_request(&self, delivery: &Delivery) -> Result<Request, Error> {
-        let reject_options = *self.element.config().queue_consumer().reject();
+async fn drop_expired(delivery: &Delivery) -> Result<(), Error> {
+    match delivery.reject(BasicRejectOptions { requeue: false }).await {
+        Ok(()) => Ok(()),
+        Err(error) => Err(Error::new(
+            ErrorKind::AmqpFailure,
+            format!("failed to drop expired delivery: {}", error),
+        )),
+    }
+}
 
-        let request_data = match std::str::from_utf8(delivery.data.as_slice()) {
-            Ok(request_data) => request_data,
-            Err(error) => {
-                return match delivery.reject(reject_options).await {
-                    Ok(_) => Err(Error::new(
-                        ErrorKind::MalformedRequest,
-                        format!("delivery is not an utf8 string: {}", error),
-                    )),
-                    Err(error) => Err(Error::new(
-                        ErrorKind::AmqpFailure,
-                        format!("failed to reject delivery: {}", error),
-                    )),
-                };
-            }
-        };
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-        let raw_request = match serde_json::from_str::<Map<String, Value>>(request_data) {
-            Ok(raw_request) => raw_request,
-            Err(error) => {
-                return match delivery.reject(reject_options).await {
-                    Ok(()) => Err(Error::new(
-                        ErrorKind::MalformedRequest,
-                        format!("delivery is not a json object: {}", error),
-                    )),
-                    Err(error) => Err(Error::new(
-                        ErrorKind::AmqpFailure,
-                        format!("failed to reject delivery: {}", error),
-                    )),
-                };
-            }
-        };
+    use async_trait::async_trait;
+    use lapin::BasicProperties;
+    use serde_json::{json, Value};
+    use tokio::task::JoinHandle;
+    use tokio::time::Duration;
 
-        let mut request = match sanitize(raw_request, self.element.actions()) {
-            Ok(request) => request,
-            Err(error) => {
-                return match delivery.reject(reject_options).await {
-                    Ok(()) => Err(Error::new(
-                        ErrorKind::MalformedRequest,
-                        format!("request sanitization failure: {}", error),
-                    )),
-                    Err(error) => Err(Error::new(
-                        ErrorKind::AmqpFailure,
-                        format!("failed to reject delivery: {}", error),
-                    )),
-                };
-            }
-        };
+    use crate::api::input::disposition::{Disposition, FailureClass};
+    use crate::api::input::idempotency_store::InMemoryIdempotencyStore;
+    use crate::api::input::input_element::{ActionAccess, RequestHandler};
+    use crate::api::input::middleware::Next;
+    use crate::api::input::retry_policy::RetryPolicy;
+    use crate::api::shutdown::ApiHandle;
+    use crate::test_support;
+    use crate::test_support::fake_broker::{self, FakeBroker, Publication, Settlement};
 
-        request = match self.authorizer.authorize(request) {
-            Ok(request) => request,
-            Err(error) => {
-                return match delivery.reject(reject_options).await {
-                    Ok(()) => Err(Error::new(
-                        ErrorKind::MalformedRequest,
-                        format!("request sanitization failure: {}", error),
-                    )),
-                    Err(error) => Err(Error::new(
-                        ErrorKind::AmqpFailure,
-                        format!("failed to reject delivery: {}", error),
-                    )),
-                };
+    use super::*;
+
+    struct RejectingAuthorizer;
+
+    /// Records the errors of the following stages.
+    #[derive(Default)]
+    struct RecordingMiddleware {
+        errors: std::sync::Mutex<Vec<ErrorKind>>,
+    }
+
+    #[async_trait]
+    impl Middleware<()> for RecordingMiddleware {
+        async fn handle(&self, request: Request, next: Next<()>) -> Result<RequestResult, Error> {
+            let result = next.run(request).await;
+
+            if let Err(error) = &result {
+                self.errors.lock().unwrap().push(error.kind());
             }
+
+            result
+        }
+    }
+
+    #[async_trait]
+    impl Authorizer for RejectingAuthorizer {
+        async fn authorize(&self, _: Request) -> Result<Request, Error> {
+            Err(Error::new(ErrorKind::AuthorizationFailure, "not authorized"))
+        }
+    }
+
+    fn handler(result: fn() -> Result<RequestResult, Error>) -> RequestHandler<()> {
+        Arc::new(move |_, _| Box::pin(async move { result() }))
+    }
+
+    /// Handles requests for longer than the tests run.
+    fn slow_handler() -> RequestHandler<()> {
+        Arc::new(|_, _| {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+
+                Ok(RequestResult::Ok(json!("pong")))
+            })
+        })
+    }
+
+    fn element(result: fn() -> Result<RequestResult, Error>) -> InputElement<()> {
+        let mut element = test_support::input_element("requests", &["ping"], handler(result));
+        element.set_action_access("ping", ActionAccess::Public);
+
+        element
+    }
+
+    /// The api handle is returned so the dispatch is not shut down.
+    async fn run_dispatch(
+        broker: &FakeBroker,
+        element: InputElement<()>,
+    ) -> (JoinHandle<Result<(), Error>>, ApiHandle) {
+        run_dispatch_with(broker, element, Vec::new()).await
+    }
+
+    async fn run_dispatch_with(
+        broker: &FakeBroker,
+        element: InputElement<()>,
+        global_middlewares: Vec<Arc<dyn Middleware<()>>>,
+    ) -> (JoinHandle<Result<(), Error>>, ApiHandle) {
+        let connection_supervisor = broker.connection_supervisor();
+        let channel = connection_supervisor.try_get_channel().await.unwrap();
+        let (logic_request_sender, _) = async_channel::unbounded();
+
+        let dispatch = AmqpRequestDispatch::new(
+            channel,
+            connection_supervisor,
+            element,
+            Arc::new(RejectingAuthorizer),
+            global_middlewares,
+            logic_request_sender,
+            test_support::state_tracker_client().await,
+        );
+        let api_handle = ApiHandle::new(test_support::state_tracker_client().await);
+
+        let running_dispatch = tokio::spawn(dispatch.run(api_handle.input_signal()));
+
+        fake_broker::wait_until("the queue is consumed", || {
+            broker.consumers_of("requests") > 0
+        })
+        .await;
+
+        (running_dispatch, api_handle)
+    }
+
+    fn deliver_request(broker: &FakeBroker) {
+        deliver_request_with(broker, BasicProperties::default());
+    }
+
+    fn deliver_request_with(broker: &FakeBroker, properties: BasicProperties) {
+        let request = json!({ "header": { "element": "requests", "action": "ping" } });
+
+        assert!(broker.deliver(
+            "requests",
+            properties
+                .with_reply_to(ShortString::from("replies"))
+                .with_correlation_id(ShortString::from("1")),
+            request.to_string().as_bytes(),
+        ));
+    }
+
+    fn publications_to(broker: &FakeBroker, routing_key: &str) -> Vec<Publication> {
+        broker
+            .publications()
+            .into_iter()
+            .filter(|publication| publication.routing_key == routing_key)
+            .collect()
+    }
+
+    fn reply_of(publication: &Publication) -> Value {
+        serde_json::from_slice(publication.data.as_slice()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn consumes_queue_again_once_disconnected() {
+        let broker = FakeBroker::start().await;
+        let (running_dispatch, _api_handle) =
+            run_dispatch(&broker, element(|| Ok(RequestResult::Ok(json!("pong"))))).await;
+
+        broker.set_refusing(true);
+        broker.disconnect();
+        fake_broker::wait_until("reconnecting is retried", || {
+            broker.refused_connections() >= 2
+        })
+        .await;
+        broker.set_refusing(false);
+
+        fake_broker::wait_until("the queue is consumed again", || {
+            broker.consumers_of("requests") == 2
+        })
+        .await;
+
+        deliver_request(&broker);
+
+        fake_broker::wait_until("the request is replied", || {
+            !publications_to(&broker, "replies").is_empty()
+        })
+        .await;
+
+        let reply = &publications_to(&broker, "replies")[0];
+        assert_eq!(reply_of(reply), json!({ "Ok": "pong" }));
+        assert_eq!(
+            reply.properties.correlation_id().as_ref().map(ShortString::as_str),
+            Some("1")
+        );
+
+        running_dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn does_not_reply_retried_requests() {
+        let broker = FakeBroker::start().await;
+        let mut element = element(|| retry_policy::retryable("busy"));
+        element.set_retry_policy(RetryPolicy::new(3, 1000, 1000, "dead_letters".to_string(), None));
+        let (running_dispatch, _api_handle) = run_dispatch(&broker, element).await;
+
+        deliver_request(&broker);
+
+        fake_broker::wait_until("the request is retried", || {
+            !publications_to(&broker, "requests.retry.1").is_empty()
+        })
+        .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(publications_to(&broker, "replies").is_empty());
+
+        running_dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn dead_letters_permanent_failures_without_retrying_them() {
+        let broker = FakeBroker::start().await;
+        let mut element = element(|| retry_policy::permanent("unknown item"));
+        element.set_retry_policy(RetryPolicy::new(3, 1000, 1000, "dead_letters".to_string(), None));
+        let (running_dispatch, _api_handle) = run_dispatch(&broker, element).await;
+
+        deliver_request(&broker);
+
+        fake_broker::wait_until("the request is replied", || {
+            !publications_to(&broker, "replies").is_empty()
+        })
+        .await;
+
+        let dead_letters = publications_to(&broker, "requests");
+        assert_eq!(dead_letters.len(), 1);
+        assert_eq!(dead_letters[0].exchange, "dead_letters");
+        assert!(publications_to(&broker, "requests.retry.1").is_empty());
+        assert_eq!(
+            reply_of(&publications_to(&broker, "replies")[0]),
+            json!({ "Err": { "kind": "InternalFailure", "message": "unknown item" } })
+        );
+
+        running_dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn replies_requests_once_settled_for_good() {
+        let broker = FakeBroker::start().await;
+        let mut element = element(|| retry_policy::retryable("busy"));
+        element.set_disposition(FailureClass::Transient, Disposition::Drop);
+        let (running_dispatch, _api_handle) = run_dispatch(&broker, element).await;
+
+        deliver_request(&broker);
+
+        fake_broker::wait_until("the request is replied", || {
+            !publications_to(&broker, "replies").is_empty()
+        })
+        .await;
+
+        assert_eq!(
+            reply_of(&publications_to(&broker, "replies")[0]),
+            json!({ "Err": { "kind": "InternalFailure", "message": "busy" } })
+        );
+
+        running_dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn does_not_reply_requeued_requests() {
+        let broker = FakeBroker::start().await;
+        let mut element = element(|| retry_policy::retryable("busy"));
+        element.set_disposition(FailureClass::Transient, Disposition::Requeue);
+        let (running_dispatch, _api_handle) = run_dispatch(&broker, element).await;
+
+        deliver_request(&broker);
+
+        fake_broker::wait_until("the request is requeued", || {
+            !broker.settlements().is_empty()
+        })
+        .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert!(publications_to(&broker, "replies").is_empty());
+
+        running_dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn postpones_duplicates_of_requests_in_progress_without_retrying_them() {
+        let broker = FakeBroker::start().await;
+        let mut element = test_support::input_element("requests", &["ping"], slow_handler());
+        element.set_action_access("ping", ActionAccess::Public);
+        element.set_idempotency_store(Arc::new(InMemoryIdempotencyStore::new(
+            8,
+            Duration::from_secs(60),
+        )));
+        // Any retry would dead-letter the duplicate.
+        element.set_retry_policy(RetryPolicy::new(1, 1000, 1000, "dead_letters".to_string(), None));
+        let (running_dispatch, _api_handle) = run_dispatch(&broker, element).await;
+
+        for _ in 0..2 {
+            deliver_request_with(
+                &broker,
+                BasicProperties::default().with_message_id(ShortString::from("m1")),
+            );
+        }
+
+        fake_broker::wait_until("the duplicate is settled", || {
+            !broker.settlements().is_empty()
+        })
+        .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(broker.settlements(), vec![Settlement::Ack(2)]);
+        let postponed = publications_to(&broker, "requests.postponed");
+        assert_eq!(postponed.len(), 1);
+        assert_eq!(postponed[0].exchange, "");
+        assert_eq!(
+            postponed[0].properties.expiration().as_ref().map(ShortString::as_str),
+            Some("1000")
+        );
+        assert!(postponed[0]
+            .properties
+            .headers()
+            .as_ref()
+            .is_none_or(|headers| !headers.contains_key(retry_policy::ATTEMPT_HEADER)));
+        assert!(broker
+            .publications()
+            .iter()
+            .all(|publication| publication.exchange != "dead_letters"));
+        assert!(publications_to(&broker, "replies").is_empty());
+
+        running_dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn stops_consuming_while_max_concurrent_requests_are_handled() {
+        let broker = FakeBroker::start().await;
+        let started_requests = Arc::new(AtomicUsize::new(0));
+        let finishable_requests = Arc::new(Semaphore::new(0));
+        let handler: RequestHandler<()> = {
+            let started_requests = started_requests.clone();
+            let finishable_requests = finishable_requests.clone();
+
+            Arc::new(move |_, _| {
+                let finishable_requests = finishable_requests.clone();
+                started_requests.fetch_add(1, Ordering::SeqCst);
+
+                Box::pin(async move {
+                    finishable_requests.acquire().await.unwrap().forget();
+
+                    Ok(RequestResult::Ok(json!("pong")))
+                })
+            })
         };
+        let mut element = test_support::limited_input_element("requests", &["ping"], handler, 2);
+        element.set_action_access("ping", ActionAccess::Public);
+        let (running_dispatch, _api_handle) = run_dispatch(&broker, element).await;
+
+        assert_eq!(broker.prefetch_counts(), vec![2]);
+
+        for _ in 0..3 {
+            deliver_request(&broker);
+        }
+
+        fake_broker::wait_until("the first requests are handled", || {
+            started_requests.load(Ordering::SeqCst) == 2
+        })
+        .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(started_requests.load(Ordering::SeqCst), 2);
+
+        finishable_requests.add_permits(1);
+
+        fake_broker::wait_until("the last request is handled", || {
+            started_requests.load(Ordering::SeqCst) == 3
+        })
+        .await;
+        assert_eq!(broker.settlements().len(), 1);
+
+        finishable_requests.add_permits(2);
+        fake_broker::wait_until("every request is settled", || {
+            broker.settlements().len() == 3
+        })
+        .await;
+
+        running_dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn drops_and_replies_timed_out_requests() {
+        let broker = FakeBroker::start().await;
+        let mut element = test_support::input_element("requests", &["ping"], slow_handler());
+        element.set_action_access("ping", ActionAccess::Public);
+        element.set_timeout(Duration::from_millis(50));
+        element.set_retry_policy(RetryPolicy::new(3, 1000, 1000, "dead_letters".to_string(), None));
+        let (running_dispatch, _api_handle) = run_dispatch(&broker, element).await;
+
+        deliver_request(&broker);
+
+        fake_broker::wait_until("the request is replied", || {
+            !publications_to(&broker, "replies").is_empty()
+        })
+        .await;
+
+        assert_eq!(
+            broker.settlements(),
+            vec![Settlement::Reject {
+                delivery_tag: 1,
+                requeue: false
+            }]
+        );
+        assert!(publications_to(&broker, "requests.retry.1").is_empty());
+        assert_eq!(
+            reply_of(&publications_to(&broker, "replies")[0]),
+            json!({ "Err": { "kind": "InternalFailure", "message": "request handling timed out" } })
+        );
+
+        running_dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn runs_global_middlewares_around_authorization() {
+        let broker = FakeBroker::start().await;
+        let mut element = element(|| Ok(RequestResult::Ok(json!("pong"))));
+        element.set_action_access("ping", ActionAccess::Authenticated);
+        let recording_middleware = Arc::new(RecordingMiddleware::default());
+        let (running_dispatch, _api_handle) =
+            run_dispatch_with(&broker, element, vec![recording_middleware.clone()]).await;
+
+        deliver_request(&broker);
+
+        fake_broker::wait_until("the request is replied", || {
+            !publications_to(&broker, "replies").is_empty()
+        })
+        .await;
+
+        assert_eq!(
+            *recording_middleware.errors.lock().unwrap(),
+            vec![ErrorKind::AuthorizationFailure]
+        );
+
+        running_dispatch.abort();
+    }
+
+    #[tokio::test]
+    async fn runs_global_middlewares_around_parsing() {
+        let broker = FakeBroker::start().await;
+        let recording_middleware = Arc::new(RecordingMiddleware::default());
+        let (running_dispatch, _api_handle) = run_dispatch_with(
+            &broker,
+            element(|| Ok(RequestResult::Ok(json!("pong")))),
+            vec![recording_middleware.clone()],
+        )
+        .await;
+
+        assert!(broker.deliver(
+            "requests",
+            BasicProperties::default()
+                .with_reply_to(ShortString::from("replies"))
+                .with_correlation_id(ShortString::from("1")),
+            b"not json",
+        ));
+
+        fake_broker::wait_until("the request is replied", || {
+            !publications_to(&broker, "replies").is_empty()
+        })
+        .await;
+
+        assert_eq!(
+            *recording_middleware.errors.lock().unwrap(),
+            vec![ErrorKind::MalformedRequest]
+        );
+        assert_eq!(
+            reply_of(&publications_to(&broker, "replies")[0])["Err"]["kind"],
+            json!("MalformedRequest")
+        );
 
-        Ok(request)
+        running_dispatch.abort();
     }
 }
@@ -6,6 +6,8 @@ use lapin::options::BasicPublishOptions;
 use lapin::types::ShortString;
 use lapin::{BasicProperties, Channel};
 
+use crate::api::input::disposition;
+use crate::api::input::request_result_error_extension;
 use crate::error::{Error, ErrorKind};
 
 pub struct AmqpRequestReplier<'reply> {
@@ -28,8 +30,20 @@ impl<'reply> AmqpRequestReplier<'reply> {
     }
 
     pub async fn reply(&'reply self, result: RequestResult) -> Result<(), Error> {
-        let options = BasicPublishOptions::default();
-        let payload = match serde_json::to_vec(&result) {
+        match serde_json::to_vec(&result) {
+            Ok(payload) => self.publish(payload).await,
+            Err(error) => Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("failed to serialize result: {}", error),
+            )),
+        }
+    }
+
+    /// Replies the error as a failed result, keeping its policy violation if any.
+    pub async fn reply_error(&'reply self, error: Error) -> Result<(), Error> {
+        let payload = match request_result_error_extension::error_result_of(error)
+            .and_then(|result| serde_json::to_vec(&result))
+        {
             Ok(payload) => payload,
             Err(error) => {
                 return Err(Error::new(
@@ -39,6 +53,12 @@ impl<'reply> AmqpRequestReplier<'reply> {
             }
         };
 
+        self.publish(payload).await
+    }
+
+    async fn publish(&'reply self, payload: Vec<u8>) -> Result<(), Error> {
+        let options = BasicPublishOptions::default();
+
         match self
             .channel
             .basic_publish(
@@ -50,7 +70,7 @@ impl<'reply> AmqpRequestReplier<'reply> {
             )
             .await
         {
-            Ok(_) => Ok(()),
+            Ok(publisher_confirm) => disposition::try_confirm(publisher_confirm, "reply").await,
             Err(error) => Err(Error::new(
                 ErrorKind::AmqpFailure,
                 format!("failed to send reply: {}", error),
@@ -1,44 +1,20 @@
+use async_trait::async_trait;
+
 use crate::api::input::request::Request;
-use crate::api::input::request_header::RequestHeader;
-use crate::api::input::token_validator;
-use crate::api::input::token_validator::TokenValidator;
-use crate::config::openid_connect_config::OpenIdConnectConfig;
+use crate::api::shutdown::ShutdownSignal;
 use crate::error::Error;
 
-pub struct Authorizer {
-    token_validator: TokenValidator,
-}
-
-impl Authorizer {
-    pub fn new(token_validator: TokenValidator) -> Authorizer {
-        Authorizer { token_validator }
-    }
-
-    pub fn authorize(&self, mut request: Request) -> Result<Request, Error> {
-        let raw_token = request.try_get_token()?;
-
-        let token = self.token_validator.validate(raw_token.as_str())?;
-
-        let header = request.try_get_header()?;
+/// Decides whether a sanitized request may be handled. Implementations may enrich
+/// the request, e.g. by setting its authorized token.
+#[async_trait]
+pub trait Authorizer: Send + Sync {
+    async fn authorize(&self, request: Request) -> Result<Request, Error>;
 
-        let required_permission = permission_from_header(header);
+    /// Background work of the authorizer, such as refreshing its keys, run by the api
+    /// along with the inputs. Must not return before the shutdown has been requested.
+    async fn run(&self, mut shutdown_signal: ShutdownSignal) -> Result<(), Error> {
+        shutdown_signal.requested().await;
 
-        token.has_permission(&required_permission)?;
-
-        request.authorized_token = Some(token);
-
-        Ok(request)
+        Ok(())
     }
 }
-
-fn permission_from_header(header: RequestHeader) -> String {
-    format!("{}:{}", header.action(), header.element())
-}
-
-pub async fn try_generate_authorizer(
-    openid_connect: OpenIdConnectConfig,
-) -> Result<Authorizer, Error> {
-    let token_validator = token_validator::try_generate_token_validator(openid_connect).await?;
-
-    Ok(Authorizer::new(token_validator))
-}
@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures_util::future;
+
+use crate::api::input::authorizer::Authorizer;
+use crate::api::input::request::Request;
+use crate::api::shutdown::ShutdownSignal;
+use crate::error::{Error, ErrorKind};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChainMode {
+    /// The request is authorized by the first authorizer which accepts it.
+    FirstMatch,
+    /// The request is passed through every authorizer, all of them must accept it.
+    AllMustPass,
+}
+
+/// Combines several authorizers, which are run in the given order.
+pub struct AuthorizerChain {
+    authorizers: Vec<Arc<dyn Authorizer>>,
+    mode: ChainMode,
+}
+
+impl AuthorizerChain {
+    pub fn new(authorizers: Vec<Arc<dyn Authorizer>>, mode: ChainMode) -> AuthorizerChain {
+        AuthorizerChain { authorizers, mode }
+    }
+}
+
+#[async_trait]
+impl Authorizer for AuthorizerChain {
+    async fn authorize(&self, mut request: Request) -> Result<Request, Error> {
+        match self.mode {
+            ChainMode::FirstMatch => {
+                let mut latest_error = Error::new(
+                    ErrorKind::AuthorizationFailure,
+                    "authorizer chain is empty",
+                );
+
+                for authorizer in self.authorizers.iter() {
+                    match authorizer.authorize(request.clone()).await {
+                        Ok(request) => return Ok(request),
+                        Err(error) => latest_error = error,
+                    }
+                }
+
+                Err(latest_error)
+            }
+            ChainMode::AllMustPass => {
+                // Otherwise every request would pass.
+                if self.authorizers.is_empty() {
+                    return Err(Error::new(
+                        ErrorKind::AuthorizationFailure,
+                        "authorizer chain is empty",
+                    ));
+                }
+
+                for authorizer in self.authorizers.iter() {
+                    request = authorizer.authorize(request).await?;
+                }
+
+                Ok(request)
+            }
+        }
+    }
+
+    /// Runs the background work of every authorizer of the chain.
+    async fn run(&self, shutdown_signal: ShutdownSignal) -> Result<(), Error> {
+        future::try_join_all(
+            self.authorizers
+                .iter()
+                .map(|authorizer| authorizer.run(shutdown_signal.clone())),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use serde_json::Map;
+
+    use super::*;
+
+    /// Accepts requests by appending its name to their tenant, or rejects them.
+    struct StubAuthorizer {
+        name: &'static str,
+        accepting: bool,
+        calls: AtomicUsize,
+    }
+
+    impl StubAuthorizer {
+        fn new(name: &'static str, accepting: bool) -> Arc<StubAuthorizer> {
+            Arc::new(StubAuthorizer {
+                name,
+                accepting,
+                calls: AtomicUsize::new(0),
+            })
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl Authorizer for StubAuthorizer {
+        async fn authorize(&self, mut request: Request) -> Result<Request, Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+
+            if !self.accepting {
+                return Err(Error::new(ErrorKind::AuthorizationFailure, self.name));
+            }
+
+            request.tenant = Some(format!("{}{}", request.tenant.unwrap_or_default(), self.name));
+
+            Ok(request)
+        }
+    }
+
+    fn chain(authorizers: &[&Arc<StubAuthorizer>], mode: ChainMode) -> AuthorizerChain {
+        AuthorizerChain::new(
+            authorizers
+                .iter()
+                .map(|authorizer| (*authorizer).clone() as Arc<dyn Authorizer>)
+                .collect(),
+            mode,
+        )
+    }
+
+    fn request() -> Request {
+        Request::new(Map::new())
+    }
+
+    #[tokio::test]
+    async fn stops_at_first_accepting_authorizer() {
+        let rejecting = StubAuthorizer::new("a", false);
+        let accepting = StubAuthorizer::new("b", true);
+        let unreached = StubAuthorizer::new("c", true);
+
+        let request = chain(&[&rejecting, &accepting, &unreached], ChainMode::FirstMatch)
+            .authorize(request())
+            .await
+            .unwrap();
+
+        assert_eq!(request.tenant.as_deref(), Some("b"));
+        assert_eq!(unreached.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn fails_with_latest_error_once_every_authorizer_rejected() {
+        let first = StubAuthorizer::new("a", false);
+        let second = StubAuthorizer::new("b", false);
+
+        let error = chain(&[&first, &second], ChainMode::FirstMatch)
+            .authorize(request())
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.message, "b");
+        assert_eq!(first.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn passes_request_through_every_authorizer() {
+        let first = StubAuthorizer::new("a", true);
+        let second = StubAuthorizer::new("b", true);
+
+        let request = chain(&[&first, &second], ChainMode::AllMustPass)
+            .authorize(request())
+            .await
+            .unwrap();
+
+        assert_eq!(request.tenant.as_deref(), Some("ab"));
+    }
+
+    #[tokio::test]
+    async fn fails_on_first_rejection() {
+        let accepting = StubAuthorizer::new("a", true);
+        let rejecting = StubAuthorizer::new("b", false);
+        let unreached = StubAuthorizer::new("c", true);
+
+        let error = chain(&[&accepting, &rejecting, &unreached], ChainMode::AllMustPass)
+            .authorize(request())
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.message, "b");
+        assert_eq!(unreached.calls(), 0);
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_when_empty() {
+        for mode in [ChainMode::FirstMatch, ChainMode::AllMustPass] {
+            let error = chain(&[], mode).authorize(request()).await.unwrap_err();
+
+            assert_eq!(error.kind(), ErrorKind::AuthorizationFailure);
+            assert_eq!(error.message, "authorizer chain is empty");
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lapin::message::Delivery;
+use lapin::types::AMQPValue;
+use tokio::time::{Duration, Instant};
+
+use crate::api::input::request_header::RequestHeader;
+
+/// Timestamp in milliseconds of the deadline set through the delivery's 'expiration',
+/// kept across retries since they overwrite the expiration.
+pub const DEADLINE_HEADER: &str = "x-deadline";
+
+/// Deadline sent by the client through the delivery's 'expiration', which counts
+/// from the delivery's 'timestamp' whenever it has one, otherwise from now.
+pub fn delivery_deadline_of(delivery: &Delivery) -> Option<Instant> {
+    let now_in_milliseconds = current_timestamp_in_milliseconds();
+
+    expiration_deadline_of(delivery, now_in_milliseconds)
+        .map(|deadline| instant_of(deadline, Instant::now(), now_in_milliseconds))
+}
+
+/// Earliest of the delivery's deadline, the header's 'deadline' and the handler's timeout.
+pub fn deadline_of(
+    header: &RequestHeader,
+    delivery_deadline: Option<Instant>,
+    timeout: Option<Duration>,
+) -> Option<Instant> {
+    let now = Instant::now();
+
+    let header_deadline = header
+        .deadline()
+        .map(|deadline| instant_of(deadline, now, current_timestamp_in_milliseconds()));
+
+    let timeout_deadline = timeout.map(|timeout| now + timeout);
+
+    [header_deadline, delivery_deadline, timeout_deadline]
+        .into_iter()
+        .flatten()
+        .min()
+}
+
+/// Timestamp in milliseconds of the deadline set through the delivery's 'expiration',
+/// taken from the 'x-deadline' header of retried deliveries.
+pub fn expiration_timestamp_of(delivery: &Delivery) -> Option<u64> {
+    expiration_deadline_of(delivery, current_timestamp_in_milliseconds())
+}
+
+fn expiration_deadline_of(delivery: &Delivery, now_in_milliseconds: u64) -> Option<u64> {
+    let carried_deadline = delivery
+        .properties
+        .headers()
+        .as_ref()
+        .and_then(|headers| headers.inner().get(DEADLINE_HEADER).cloned());
+
+    match carried_deadline {
+        Some(AMQPValue::LongLongInt(deadline)) => return Some(deadline.max(0) as u64),
+        Some(AMQPValue::Timestamp(deadline)) => return Some(deadline),
+        _ => (),
+    }
+
+    let expiration = delivery
+        .properties
+        .expiration()
+        .as_ref()
+        .and_then(|expiration| expiration.as_str().parse::<u64>().ok())?;
+
+    let start = match delivery.properties.timestamp() {
+        Some(timestamp) => timestamp.saturating_mul(1000),
+        None => now_in_milliseconds,
+    };
+
+    Some(start.saturating_add(expiration))
+}
+
+pub fn has_expired(deadline: Option<Instant>) -> bool {
+    match deadline {
+        Some(deadline) => deadline <= Instant::now(),
+        None => false,
+    }
+}
+
+fn instant_of(timestamp_in_milliseconds: u64, now: Instant, now_in_milliseconds: u64) -> Instant {
+    now + Duration::from_millis(timestamp_in_milliseconds.saturating_sub(now_in_milliseconds))
+}
+
+fn current_timestamp_in_milliseconds() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_millis() as u64,
+        Err(_) => 0,
+    }
+}
@@ -0,0 +1,669 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use cooplan_amqp_api_shared::api::input::request_result_error::RequestResultErrorKind;
+use jsonwebtoken::get_current_timestamp;
+use lapin::message::Delivery;
+use lapin::options::{BasicAckOptions, BasicPublishOptions, BasicRejectOptions};
+use lapin::publisher_confirm::{Confirmation, PublisherConfirm};
+use lapin::types::{AMQPValue, FieldTable, LongString, ShortString};
+use lapin::Channel;
+
+use crate::api::input::deadline;
+use crate::api::input::policy::PolicyViolation;
+use crate::api::input::retry_policy;
+use crate::api::input::retry_policy::RetryPolicy;
+use crate::error::{Error, ErrorKind};
+
+pub const ERROR_KIND_HEADER: &str = "x-error-kind";
+pub const ERROR_MESSAGE_HEADER: &str = "x-error-message";
+pub const ELEMENT_HEADER: &str = "x-element";
+pub const FAILED_AT_HEADER: &str = "x-failed-at";
+pub const POLICY_HEADER: &str = "x-policy";
+pub const POLICY_REASON_HEADER: &str = "x-policy-reason";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FailureClass {
+    /// Poison messages: not utf8, not json, not sanitizable or rejected as malformed
+    /// by the handler. Delivering them again leads to the same failure.
+    Malformed,
+    /// Requests whose token or permissions are not valid, or that violate a policy.
+    Unauthorized,
+    /// Any other failure, which may not happen again.
+    Transient,
+    /// Handler failures marked as permanent, which are never retried.
+    Permanent,
+    /// Requests whose deadline has been reached while being handled, which the
+    /// client is no longer waiting for. Dropped by default.
+    TimedOut,
+}
+
+impl From<ErrorKind> for FailureClass {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::MalformedRequest | ErrorKind::SanitizationFailure => FailureClass::Malformed,
+            ErrorKind::TokenDecodingFailure
+            | ErrorKind::MalformedToken
+            | ErrorKind::InvalidToken
+            | ErrorKind::RevokedToken
+            | ErrorKind::PermissionNotFound
+            | ErrorKind::AuthorizationFailure => FailureClass::Unauthorized,
+            ErrorKind::RetryableFailure => FailureClass::Transient,
+            ErrorKind::PermanentFailure => FailureClass::Permanent,
+            ErrorKind::TimedOutRequest => FailureClass::TimedOut,
+            _ => FailureClass::Transient,
+        }
+    }
+}
+
+impl From<RequestResultErrorKind> for FailureClass {
+    fn from(kind: RequestResultErrorKind) -> Self {
+        match kind {
+            RequestResultErrorKind::MalformedRequest => FailureClass::Malformed,
+            RequestResultErrorKind::InternalFailure => FailureClass::Transient,
+        }
+    }
+}
+
+/// What happens to a delivery whose request has failed.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Disposition {
+    /// Rejected with the reject options of the input element's config.
+    #[default]
+    Reject,
+    Requeue,
+    Drop,
+    /// Published to the exchange along with the failure headers, then acknowledged once
+    /// the broker has confirmed the publication. The routing key defaults to the delivery's one.
+    DeadLetter {
+        exchange: String,
+        routing_key: Option<String>,
+    },
+}
+
+/// How a failed delivery has been settled, its request being replied only once
+/// the outcome is final.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Dropped, rejected without being requeued or dead-lettered.
+    Final,
+    /// Requeued or published into a delay queue, hence delivered again.
+    Redelivered,
+}
+
+/// Failure of a request, as described by the headers of dead-lettered messages.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    class: FailureClass,
+    kind: String,
+    message: String,
+    violation: Option<PolicyViolation>,
+}
+
+impl Failure {
+    pub fn new(class: FailureClass, kind: String, message: String) -> Failure {
+        Failure {
+            class,
+            kind,
+            message,
+            violation: None,
+        }
+    }
+
+    pub fn class(&self) -> FailureClass {
+        self.class
+    }
+
+    pub fn kind(&self) -> &str {
+        self.kind.as_str()
+    }
+
+    pub fn message(&self) -> &str {
+        self.message.as_str()
+    }
+
+    pub fn violation(&self) -> Option<&PolicyViolation> {
+        self.violation.as_ref()
+    }
+}
+
+impl From<&Error> for Failure {
+    fn from(error: &Error) -> Self {
+        let mut failure = Failure::new(
+            error.kind().into(),
+            format!("{:?}", error.kind()),
+            error.message.clone(),
+        );
+        failure.violation = error.violation().cloned();
+
+        failure
+    }
+}
+
+/// Settles the deliveries of an input element whose requests have failed.
+/// Transient failures are retried instead whenever the element has a retry policy,
+/// whose dead-letter disposition applies to permanent failures without any of their own.
+pub struct DeliveryDisposer {
+    element: String,
+    dispositions: HashMap<FailureClass, Disposition>,
+    reject_options: BasicRejectOptions,
+    retry_policy: Option<RetryPolicy>,
+    postponement_delay: Duration,
+}
+
+impl DeliveryDisposer {
+    pub fn new(
+        element: String,
+        dispositions: HashMap<FailureClass, Disposition>,
+        reject_options: BasicRejectOptions,
+        retry_policy: Option<RetryPolicy>,
+        postponement_delay: Duration,
+    ) -> DeliveryDisposer {
+        DeliveryDisposer {
+            element,
+            dispositions,
+            reject_options,
+            retry_policy,
+            postponement_delay,
+        }
+    }
+
+    pub fn disposition(&self, class: FailureClass) -> &Disposition {
+        static DEFAULT_DISPOSITION: Disposition = Disposition::Reject;
+        static TIMED_OUT_DISPOSITION: Disposition = Disposition::Drop;
+
+        match (self.dispositions.get(&class), class) {
+            (Some(disposition), _) => disposition,
+            (None, FailureClass::TimedOut) => &TIMED_OUT_DISPOSITION,
+            (None, _) => &DEFAULT_DISPOSITION,
+        }
+    }
+
+    /// Deliveries failing to be disposed have been rejected whenever possible,
+    /// with the reject options of the input element's config.
+    pub async fn dispose(
+        &self,
+        channel: &Channel,
+        delivery: &Delivery,
+        failure: &Failure,
+    ) -> Result<Outcome, Error> {
+        match (failure.class(), &self.retry_policy) {
+            (FailureClass::Transient, Some(retry_policy)) => {
+                return self.retry(channel, delivery, failure, retry_policy).await;
+            }
+            (FailureClass::Permanent, Some(retry_policy))
+                if !self.dispositions.contains_key(&FailureClass::Permanent) =>
+            {
+                return self
+                    .dispose_with(channel, delivery, failure, retry_policy.dead_letter())
+                    .await;
+            }
+            _ => (),
+        }
+
+        self.dispose_with(channel, delivery, failure, self.disposition(failure.class()))
+            .await
+    }
+
+    /// Delivers the delivery again once the postponement delay has expired, through
+    /// the element's postponement queue. Its attempt is left as is, so postponing
+    /// never leads to dead-lettering.
+    pub async fn postpone(&self, channel: &Channel, delivery: &Delivery) -> Result<Outcome, Error> {
+        self.delay(
+            channel,
+            delivery,
+            delivery.properties.headers().clone().unwrap_or_default(),
+            retry_policy::postponement_queue_name(self.element.as_str()).as_str(),
+            self.postponement_delay.as_millis() as u64,
+        )
+        .await
+    }
+
+    async fn dispose_with(
+        &self,
+        channel: &Channel,
+        delivery: &Delivery,
+        failure: &Failure,
+        disposition: &Disposition,
+    ) -> Result<Outcome, Error> {
+        match disposition {
+            Disposition::Reject => reject(delivery, self.reject_options).await,
+            Disposition::Requeue => reject(delivery, BasicRejectOptions { requeue: true }).await,
+            Disposition::Drop => reject(delivery, BasicRejectOptions { requeue: false }).await,
+            Disposition::DeadLetter {
+                exchange,
+                routing_key,
+            } => {
+                let routing_key = match routing_key {
+                    Some(routing_key) => routing_key.as_str(),
+                    None => delivery.routing_key.as_str(),
+                };
+
+                if let Err(error) = self
+                    .dead_letter(channel, delivery, failure, exchange, routing_key)
+                    .await
+                {
+                    reject(delivery, self.reject_options).await?;
+
+                    return Err(error);
+                }
+
+                match delivery.ack(BasicAckOptions::default()).await {
+                    Ok(()) => Ok(Outcome::Final),
+                    Err(error) => Err(Error::new(
+                        ErrorKind::AmqpFailure,
+                        format!("failed to acknowledge dead-lettered delivery: {}", error),
+                    )),
+                }
+            }
+        }
+    }
+
+    /// Publishes the delivery into the delay queue of its attempt, the element's
+    /// queue being named after the element.
+    async fn retry(
+        &self,
+        channel: &Channel,
+        delivery: &Delivery,
+        failure: &Failure,
+        retry_policy: &RetryPolicy,
+    ) -> Result<Outcome, Error> {
+        let attempt = retry_policy::attempt_of(delivery);
+
+        if attempt >= retry_policy.max_attempts() {
+            log::info!(
+                "delivery of '{}' failed after {} attempts",
+                self.element,
+                attempt
+            );
+
+            return self
+                .dispose_with(channel, delivery, failure, retry_policy.dead_letter())
+                .await;
+        }
+
+        let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+        headers.insert(
+            ShortString::from(retry_policy::ATTEMPT_HEADER),
+            AMQPValue::LongUInt(attempt + 1),
+        );
+
+        self.delay(
+            channel,
+            delivery,
+            headers,
+            retry_policy::delay_queue_name(self.element.as_str(), attempt).as_str(),
+            retry_policy.delay_in_milliseconds(attempt),
+        )
+        .await
+    }
+
+    /// Publishes the delivery into the delay queue, from which it is dead-lettered back
+    /// into the element's queue once the delay has expired, then acknowledges it.
+    async fn delay(
+        &self,
+        channel: &Channel,
+        delivery: &Delivery,
+        mut headers: FieldTable,
+        delay_queue: &str,
+        delay_in_milliseconds: u64,
+    ) -> Result<Outcome, Error> {
+        // The expiration is overwritten by the delay, and removed once dead-lettered.
+        if let Some(deadline) = deadline::expiration_timestamp_of(delivery) {
+            headers.insert(
+                ShortString::from(deadline::DEADLINE_HEADER),
+                AMQPValue::LongLongInt(deadline.min(i64::MAX as u64) as i64),
+            );
+        }
+
+        let properties = delivery
+            .properties
+            .clone()
+            .with_headers(headers)
+            .with_expiration(ShortString::from(delay_in_milliseconds.to_string()));
+
+        // Mandatory, so deliveries are returned instead of being lost whenever the delay
+        // queue is missing.
+        let published = match channel
+            .basic_publish(
+                "",
+                delay_queue,
+                BasicPublishOptions {
+                    mandatory: true,
+                    ..BasicPublishOptions::default()
+                },
+                delivery.data.as_slice(),
+                properties,
+            )
+            .await
+        {
+            Ok(publisher_confirm) => try_confirm(publisher_confirm, "delayed delivery").await,
+            Err(error) => Err(Error::new(
+                ErrorKind::AmqpFailure,
+                format!("failed to publish delayed delivery: {}", error),
+            )),
+        };
+
+        if let Err(error) = published {
+            reject(delivery, self.reject_options).await?;
+
+            return Err(error);
+        }
+
+        match delivery.ack(BasicAckOptions::default()).await {
+            Ok(()) => Ok(Outcome::Redelivered),
+            Err(error) => Err(Error::new(
+                ErrorKind::AmqpFailure,
+                format!("failed to acknowledge delayed delivery: {}", error),
+            )),
+        }
+    }
+
+    async fn dead_letter(
+        &self,
+        channel: &Channel,
+        delivery: &Delivery,
+        failure: &Failure,
+        exchange: &str,
+        routing_key: &str,
+    ) -> Result<(), Error> {
+        let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+        headers.insert(
+            ShortString::from(ERROR_KIND_HEADER),
+            AMQPValue::LongString(LongString::from(failure.kind())),
+        );
+        headers.insert(
+            ShortString::from(ERROR_MESSAGE_HEADER),
+            AMQPValue::LongString(LongString::from(failure.message())),
+        );
+        headers.insert(
+            ShortString::from(ELEMENT_HEADER),
+            AMQPValue::LongString(LongString::from(self.element.as_str())),
+        );
+        headers.insert(
+            ShortString::from(FAILED_AT_HEADER),
+            AMQPValue::Timestamp(get_current_timestamp()),
+        );
+
+        if let Some(violation) = failure.violation() {
+            headers.insert(
+                ShortString::from(POLICY_HEADER),
+                AMQPValue::LongString(LongString::from(violation.policy.as_str())),
+            );
+            headers.insert(
+                ShortString::from(POLICY_REASON_HEADER),
+                AMQPValue::LongString(LongString::from(violation.reason.as_str())),
+            );
+        }
+
+        // Mandatory, so deliveries which cannot be routed are returned instead of being lost.
+        match channel
+            .basic_publish(
+                exchange,
+                routing_key,
+                BasicPublishOptions {
+                    mandatory: true,
+                    ..BasicPublishOptions::default()
+                },
+                delivery.data.as_slice(),
+                delivery.properties.clone().with_headers(headers),
+            )
+            .await
+        {
+            Ok(publisher_confirm) => try_confirm(publisher_confirm, "dead-lettered delivery").await,
+            Err(error) => Err(Error::new(
+                ErrorKind::AmqpFailure,
+                format!("failed to dead-letter delivery: {}", error),
+            )),
+        }
+    }
+}
+
+/// Waits until the broker has taken responsibility for a publication, which requires
+/// the channel to be in confirm mode. Returned publications have not been routed.
+pub(crate) async fn try_confirm(
+    publisher_confirm: PublisherConfirm,
+    publication: &str,
+) -> Result<(), Error> {
+    let error_message = match publisher_confirm.await {
+        Ok(Confirmation::Ack(None)) => return Ok(()),
+        Ok(Confirmation::Ack(Some(_))) => format!("{} could not be routed", publication),
+        Ok(Confirmation::Nack(_)) => format!("{} has been rejected by the broker", publication),
+        Ok(Confirmation::NotRequested) => {
+            format!("{} cannot be confirmed outside of confirm mode", publication)
+        }
+        Err(error) => format!("failed to confirm {}: {}", publication, error),
+    };
+
+    Err(Error::new(ErrorKind::AmqpFailure, error_message))
+}
+
+async fn reject(delivery: &Delivery, reject_options: BasicRejectOptions) -> Result<Outcome, Error> {
+    match delivery.reject(reject_options).await {
+        Ok(()) if reject_options.requeue => Ok(Outcome::Redelivered),
+        Ok(()) => Ok(Outcome::Final),
+        Err(error) => Err(Error::new(
+            ErrorKind::AmqpFailure,
+            format!("failed to reject delivery: {}", error),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_util::StreamExt;
+    use lapin::options::{BasicConsumeOptions, ConfirmSelectOptions, QueueDeclareOptions};
+    use lapin::types::FieldTable;
+    use lapin::{BasicProperties, Consumer};
+
+    use crate::api::connection_supervisor::ConnectionSupervisor;
+    use crate::test_support::fake_broker::{self, FakeBroker, Settlement};
+
+    use super::*;
+
+    /// The supervisor and the consumer are kept, so the delivery can still be settled.
+    struct ConsumedDelivery {
+        _connection_supervisor: Arc<ConnectionSupervisor>,
+        _consumer: Consumer,
+        channel: Arc<Channel>,
+        delivery: Delivery,
+    }
+
+    async fn consume_delivery(broker: &FakeBroker) -> ConsumedDelivery {
+        consume_delivery_with(broker, BasicProperties::default()).await
+    }
+
+    async fn consume_delivery_with(
+        broker: &FakeBroker,
+        properties: BasicProperties,
+    ) -> ConsumedDelivery {
+        let connection_supervisor = broker.connection_supervisor();
+        let channel = connection_supervisor.try_get_channel().await.unwrap();
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await
+            .unwrap();
+        let mut consumer = channel
+            .basic_consume(
+                "requests",
+                "requests#test",
+                BasicConsumeOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .unwrap();
+
+        assert!(broker.deliver("requests", properties, b"{}"));
+        let delivery = consumer.next().await.unwrap().unwrap();
+
+        ConsumedDelivery {
+            _connection_supervisor: connection_supervisor,
+            _consumer: consumer,
+            channel,
+            delivery,
+        }
+    }
+
+    fn dead_lettering_disposer() -> DeliveryDisposer {
+        DeliveryDisposer::new(
+            "requests".to_string(),
+            HashMap::from([(
+                FailureClass::Malformed,
+                Disposition::DeadLetter {
+                    exchange: "dead_letters".to_string(),
+                    routing_key: None,
+                },
+            )]),
+            BasicRejectOptions::default(),
+            None,
+            Duration::from_secs(1),
+        )
+    }
+
+    fn malformed_failure() -> Failure {
+        Failure::new(
+            FailureClass::Malformed,
+            "MalformedRequest".to_string(),
+            "not a request".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn acknowledges_dead_lettered_delivery_once_confirmed() {
+        let broker = FakeBroker::start().await;
+        let consumed = consume_delivery(&broker).await;
+
+        let outcome = dead_lettering_disposer()
+            .dispose(&consumed.channel, &consumed.delivery, &malformed_failure())
+            .await
+            .unwrap();
+
+        assert_eq!(outcome, Outcome::Final);
+        let publications = broker.publications();
+        assert_eq!(publications.len(), 1);
+        assert_eq!(publications[0].exchange, "dead_letters");
+        assert_eq!(publications[0].routing_key, "requests");
+
+        fake_broker::wait_until("the delivery is settled", || {
+            !broker.settlements().is_empty()
+        })
+        .await;
+        assert_eq!(broker.settlements(), vec![Settlement::Ack(1)]);
+    }
+
+    #[tokio::test]
+    async fn rejects_delivery_whose_dead_letter_is_nacked() {
+        let broker = FakeBroker::start().await;
+        broker.set_nacking(true);
+        let consumed = consume_delivery(&broker).await;
+
+        let error = dead_lettering_disposer()
+            .dispose(&consumed.channel, &consumed.delivery, &malformed_failure())
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::AmqpFailure);
+
+        fake_broker::wait_until("the delivery is settled", || {
+            !broker.settlements().is_empty()
+        })
+        .await;
+        assert_eq!(
+            broker.settlements(),
+            vec![Settlement::Reject {
+                delivery_tag: 1,
+                requeue: false
+            }]
+        );
+    }
+
+    fn retrying_disposer() -> DeliveryDisposer {
+        DeliveryDisposer::new(
+            "requests".to_string(),
+            HashMap::new(),
+            BasicRejectOptions::default(),
+            Some(RetryPolicy::new(3, 1000, 1000, "dead_letters".to_string(), None)),
+            Duration::from_secs(1),
+        )
+    }
+
+    fn transient_failure() -> Failure {
+        Failure::new(
+            FailureClass::Transient,
+            "InternalFailure".to_string(),
+            "busy".to_string(),
+        )
+    }
+
+    async fn declare_queue(consumed: &ConsumedDelivery, queue: &str) {
+        consumed
+            .channel
+            .queue_declare(queue, QueueDeclareOptions::default(), FieldTable::default())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_delivery_whose_delay_queue_is_missing() {
+        let broker = FakeBroker::start().await;
+        let consumed = consume_delivery(&broker).await;
+
+        let error = retrying_disposer()
+            .dispose(&consumed.channel, &consumed.delivery, &transient_failure())
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::AmqpFailure);
+        assert_eq!(broker.publications()[0].routing_key, "requests.retry.1");
+
+        fake_broker::wait_until("the delivery is settled", || {
+            !broker.settlements().is_empty()
+        })
+        .await;
+        assert_eq!(
+            broker.settlements(),
+            vec![Settlement::Reject {
+                delivery_tag: 1,
+                requeue: false
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn carries_deadline_of_retried_delivery() {
+        let broker = FakeBroker::start().await;
+        let consumed = consume_delivery_with(
+            &broker,
+            BasicProperties::default()
+                .with_timestamp(1_700_000_000)
+                .with_expiration(ShortString::from("60000")),
+        )
+        .await;
+        declare_queue(&consumed, "requests.retry.1").await;
+        let disposer = retrying_disposer();
+        let failure = transient_failure();
+
+        let outcome = disposer
+            .dispose(&consumed.channel, &consumed.delivery, &failure)
+            .await
+            .unwrap();
+
+        assert_eq!(outcome, Outcome::Redelivered);
+        let publications = broker.publications();
+        assert_eq!(publications[0].routing_key, "requests.retry.1");
+        let properties = &publications[0].properties;
+        assert_eq!(
+            properties.expiration().as_ref().map(ShortString::as_str),
+            Some("1000")
+        );
+        assert_eq!(
+            properties
+                .headers()
+                .as_ref()
+                .and_then(|headers| headers.inner().get(deadline::DEADLINE_HEADER).cloned()),
+            Some(AMQPValue::LongLongInt(1_700_000_060_000))
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde_json::Value;
+use tokio::time::{Duration, Instant};
+
+use crate::error::{Error, ErrorKind};
+
+/// State of a request which has already been received.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdempotencyEntry {
+    InProgress,
+    /// Serialized result of the request, replayed to duplicates.
+    Completed(Value),
+}
+
+/// Deduplicates requests by their idempotency key, which is either the header's
+/// 'idempotency_key' or the delivery's 'message_id'.
+///
+/// Only succeeding requests are completed. Failed ones are released, so that
+/// their retries are handled again.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Returns the existing entry, or claims the key by storing it as in progress.
+    async fn try_claim(&self, key: &str) -> Result<Option<IdempotencyEntry>, Error>;
+
+    async fn complete(&self, key: &str, result: Value) -> Result<(), Error>;
+
+    async fn release(&self, key: &str) -> Result<(), Error>;
+}
+
+struct StoredEntry {
+    entry: IdempotencyEntry,
+    expires_at: Instant,
+    last_use: u64,
+}
+
+struct Entries {
+    entries: HashMap<String, StoredEntry>,
+    /// Keys ordered from the least to the most recently used.
+    keys_by_use: BTreeMap<u64, String>,
+    next_use: u64,
+}
+
+/// Keeps up to 'capacity' entries for 'ttl', evicting the least recently used ones.
+/// Entries in progress expire as well, in case their handling never finishes.
+pub struct InMemoryIdempotencyStore {
+    entries: Mutex<Entries>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl InMemoryIdempotencyStore {
+    pub fn new(capacity: usize, ttl: Duration) -> InMemoryIdempotencyStore {
+        InMemoryIdempotencyStore {
+            entries: Mutex::new(Entries {
+                entries: HashMap::new(),
+                keys_by_use: BTreeMap::new(),
+                next_use: 0,
+            }),
+            capacity,
+            ttl,
+        }
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Entries>, Error> {
+        match self.entries.lock() {
+            Ok(entries) => Ok(entries),
+            Err(error) => Err(Error::new(
+                ErrorKind::InternalFailure,
+                format!("failed to lock idempotency entries: {}", error),
+            )),
+        }
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) -> Option<StoredEntry> {
+        let stored_entry = self.entries.remove(key)?;
+        self.keys_by_use.remove(&stored_entry.last_use);
+
+        Some(stored_entry)
+    }
+
+    fn insert(&mut self, key: &str, entry: IdempotencyEntry, expires_at: Instant) {
+        self.remove(key);
+
+        let last_use = self.next_use;
+        self.next_use += 1;
+
+        self.keys_by_use.insert(last_use, key.to_string());
+        self.entries.insert(
+            key.to_string(),
+            StoredEntry {
+                entry,
+                expires_at,
+                last_use,
+            },
+        );
+    }
+
+    fn evict_least_recently_used(&mut self, capacity: usize) {
+        while self.entries.len() > capacity {
+            let key = match self.keys_by_use.pop_first() {
+                Some((_, key)) => key,
+                None => return,
+            };
+
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for InMemoryIdempotencyStore {
+    async fn try_claim(&self, key: &str) -> Result<Option<IdempotencyEntry>, Error> {
+        let mut entries = self.lock()?;
+        let now = Instant::now();
+
+        if let Some(stored_entry) = entries.remove(key) {
+            if stored_entry.expires_at > now {
+                let entry = stored_entry.entry.clone();
+                entries.insert(key, stored_entry.entry, stored_entry.expires_at);
+
+                return Ok(Some(entry));
+            }
+        }
+
+        entries.insert(key, IdempotencyEntry::InProgress, now + self.ttl);
+        entries.evict_least_recently_used(self.capacity);
+
+        Ok(None)
+    }
+
+    async fn complete(&self, key: &str, result: Value) -> Result<(), Error> {
+        let mut entries = self.lock()?;
+
+        entries.insert(key, IdempotencyEntry::Completed(result), Instant::now() + self.ttl);
+        entries.evict_least_recently_used(self.capacity);
+
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), Error> {
+        self.lock()?.remove(key);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn store(capacity: usize) -> InMemoryIdempotencyStore {
+        InMemoryIdempotencyStore::new(capacity, Duration::from_secs(60))
+    }
+
+    #[tokio::test]
+    async fn claims_unknown_key() {
+        let store = store(8);
+
+        assert_eq!(store.try_claim("a").await.unwrap(), None);
+        assert_eq!(
+            store.try_claim("a").await.unwrap(),
+            Some(IdempotencyEntry::InProgress)
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_completed_result() {
+        let store = store(8);
+        store.try_claim("a").await.unwrap();
+
+        store.complete("a", json!({ "Ok": "pong" })).await.unwrap();
+
+        assert_eq!(
+            store.try_claim("a").await.unwrap(),
+            Some(IdempotencyEntry::Completed(json!({ "Ok": "pong" })))
+        );
+        assert_eq!(
+            store.try_claim("a").await.unwrap(),
+            Some(IdempotencyEntry::Completed(json!({ "Ok": "pong" })))
+        );
+    }
+
+    #[tokio::test]
+    async fn claims_released_key_again() {
+        let store = store(8);
+        store.try_claim("a").await.unwrap();
+
+        store.release("a").await.unwrap();
+
+        assert_eq!(store.try_claim("a").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn claims_expired_key_again() {
+        let store = InMemoryIdempotencyStore::new(8, Duration::from_millis(50));
+        store.try_claim("a").await.unwrap();
+        store.try_claim("b").await.unwrap();
+        store.complete("b", json!({ "Ok": "pong" })).await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(store.try_claim("a").await.unwrap(), None);
+        assert_eq!(store.try_claim("b").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_keys_beyond_capacity() {
+        let store = store(2);
+        store.try_claim("a").await.unwrap();
+        store.try_claim("b").await.unwrap();
+        // Uses 'a' again, so 'b' is the least recently used key.
+        store.try_claim("a").await.unwrap();
+
+        store.try_claim("c").await.unwrap();
+
+        assert_eq!(store.lock().unwrap().entries.len(), 2);
+        assert_eq!(store.try_claim("b").await.unwrap(), None);
+        assert_eq!(
+            store.try_claim("c").await.unwrap(),
+            Some(IdempotencyEntry::InProgress)
+        );
+    }
+}
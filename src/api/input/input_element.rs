@@ -1,7 +1,15 @@
+use std::collections::HashMap;
 use std::future::Future;
 use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::api::input::disposition::{Disposition, FailureClass};
+use crate::api::input::idempotency_store::IdempotencyStore;
+use crate::api::input::middleware::Middleware;
+use crate::api::input::permission_requirement::PermissionRequirement;
+use crate::api::input::policy::Policy;
+use crate::api::input::retry_policy::RetryPolicy;
 use crate::api::input::request::Request;
 use crate::error::{Error, ErrorKind};
 use async_channel::Sender;
@@ -9,20 +17,48 @@ use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
 use cooplan_lapin_wrapper::config::amqp_input_api::AmqpInputApi;
 use cooplan_lapin_wrapper::config::api::Api;
 
+const DEFAULT_POSTPONEMENT_DELAY: Duration = Duration::from_secs(1);
+
+/// Failures are either returned as failed results, or as errors through
+/// [retry_policy::retryable](crate::api::input::retry_policy::retryable) and
+/// [retry_policy::permanent](crate::api::input::retry_policy::permanent).
 pub type RequestHandler<LogicRequestType> = Arc<
     dyn Fn(
             Request,
             Sender<LogicRequestType>,
-        ) -> Pin<Box<dyn Future<Output = RequestResult> + Send + Sync>>
+        ) -> Pin<Box<dyn Future<Output = Result<RequestResult, Error>> + Send + Sync>>
         + Send
         + Sync,
 >;
 
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum ActionAccess {
+    /// Requests must be authorized.
+    #[default]
+    Authenticated,
+    /// Requests are never authorized, hence they have no authorized token.
+    Public,
+    /// Requests without a token are handled anonymously, whereas requests carrying
+    /// one must be authorized.
+    OptionallyAuthenticated,
+}
+
 pub struct InputElement<LogicRequestType> {
     name: String,
     request_handler: RequestHandler<LogicRequestType>,
     actions: &'static [&'static str],
     config: AmqpInputApi,
+    action_access: HashMap<&'static str, ActionAccess>,
+    permission_requirements: HashMap<&'static str, PermissionRequirement>,
+    policies: Vec<Arc<dyn Policy>>,
+    tenant_isolated: bool,
+    dispositions: HashMap<FailureClass, Disposition>,
+    retry_policy: Option<RetryPolicy>,
+    idempotency_store: Option<Arc<dyn IdempotencyStore>>,
+    postponement_delay: Duration,
+    timeout: Option<Duration>,
+    action_timeouts: HashMap<&'static str, Duration>,
+    middlewares: Vec<Arc<dyn Middleware<LogicRequestType>>>,
 }
 
 impl<LogicRequestType> InputElement<LogicRequestType> {
@@ -37,6 +73,17 @@ impl<LogicRequestType> InputElement<LogicRequestType> {
             request_handler,
             actions,
             config,
+            action_access: HashMap::new(),
+            permission_requirements: HashMap::new(),
+            policies: Vec::new(),
+            tenant_isolated: false,
+            dispositions: HashMap::new(),
+            retry_policy: None,
+            idempotency_store: None,
+            postponement_delay: DEFAULT_POSTPONEMENT_DELAY,
+            timeout: None,
+            action_timeouts: HashMap::new(),
+            middlewares: Vec::new(),
         }
     }
 
@@ -55,6 +102,130 @@ impl<LogicRequestType> InputElement<LogicRequestType> {
     pub fn config(&self) -> &AmqpInputApi {
         &self.config
     }
+
+    /// Actions are authenticated unless stated otherwise.
+    pub fn action_access(&self, action: &str) -> ActionAccess {
+        self.action_access.get(action).copied().unwrap_or_default()
+    }
+
+    pub fn action_accesses(&self) -> &HashMap<&'static str, ActionAccess> {
+        &self.action_access
+    }
+
+    pub fn set_action_access(&mut self, action: &'static str, access: ActionAccess) {
+        self.action_access.insert(action, access);
+    }
+
+    pub fn set_public_actions(&mut self, actions: &'static [&'static str]) {
+        for action in actions {
+            self.set_action_access(action, ActionAccess::Public);
+        }
+    }
+
+    pub fn set_optionally_authenticated_actions(&mut self, actions: &'static [&'static str]) {
+        for action in actions {
+            self.set_action_access(action, ActionAccess::OptionallyAuthenticated);
+        }
+    }
+
+    /// Actions without a requirement need the '{action}:{element}' permission.
+    pub fn permission_requirement(&self, action: &str) -> Option<&PermissionRequirement> {
+        self.permission_requirements.get(action)
+    }
+
+    pub fn permission_requirements(&self) -> &HashMap<&'static str, PermissionRequirement> {
+        &self.permission_requirements
+    }
+
+    pub fn set_permission_requirement(
+        &mut self,
+        action: &'static str,
+        requirement: PermissionRequirement,
+    ) {
+        self.permission_requirements.insert(action, requirement);
+    }
+
+    /// Policies are evaluated in the order they have been added.
+    pub fn policies(&self) -> &[Arc<dyn Policy>] {
+        self.policies.as_slice()
+    }
+
+    pub fn add_policy(&mut self, policy: Arc<dyn Policy>) {
+        self.policies.push(policy);
+    }
+
+    /// Requests of tenant isolated elements must state the tenant they act upon,
+    /// which authorized tokens must either belong to or have tenant-scoped
+    /// permissions for.
+    pub fn tenant_isolated(&self) -> bool {
+        self.tenant_isolated
+    }
+
+    pub fn set_tenant_isolated(&mut self, tenant_isolated: bool) {
+        self.tenant_isolated = tenant_isolated;
+    }
+
+    /// Failures without a disposition are rejected with the config's reject options.
+    pub fn dispositions(&self) -> &HashMap<FailureClass, Disposition> {
+        &self.dispositions
+    }
+
+    pub fn set_disposition(&mut self, class: FailureClass, disposition: Disposition) {
+        self.dispositions.insert(class, disposition);
+    }
+
+    /// Transient failures follow their disposition unless there is a retry policy.
+    pub fn retry_policy(&self) -> Option<&RetryPolicy> {
+        self.retry_policy.as_ref()
+    }
+
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = Some(retry_policy);
+    }
+
+    /// Requests are deduplicated only whenever there is an idempotency store.
+    pub fn idempotency_store(&self) -> Option<Arc<dyn IdempotencyStore>> {
+        self.idempotency_store.clone()
+    }
+
+    pub fn set_idempotency_store(&mut self, idempotency_store: Arc<dyn IdempotencyStore>) {
+        self.idempotency_store = Some(idempotency_store);
+    }
+
+    /// Delay before duplicates of requests in progress are delivered again.
+    pub fn postponement_delay(&self) -> Duration {
+        self.postponement_delay
+    }
+
+    pub fn set_postponement_delay(&mut self, postponement_delay: Duration) {
+        self.postponement_delay = postponement_delay;
+    }
+
+    /// Time the handler has for an action, the action's timeout taking precedence
+    /// over the element's one. Handlers without timeout are never cancelled.
+    pub fn timeout(&self, action: &str) -> Option<Duration> {
+        match self.action_timeouts.get(action) {
+            Some(timeout) => Some(*timeout),
+            None => self.timeout,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    pub fn set_action_timeout(&mut self, action: &'static str, timeout: Duration) {
+        self.action_timeouts.insert(action, timeout);
+    }
+
+    /// Middlewares run after the global ones, in the order they have been added.
+    pub fn middlewares(&self) -> &[Arc<dyn Middleware<LogicRequestType>>] {
+        self.middlewares.as_slice()
+    }
+
+    pub fn add_middleware(&mut self, middleware: Arc<dyn Middleware<LogicRequestType>>) {
+        self.middlewares.push(middleware);
+    }
 }
 
 pub fn extract_input<LogicRequestType>(
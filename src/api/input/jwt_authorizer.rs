@@ -0,0 +1,84 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use cooplan_state_tracker::state_tracker_client::StateTrackerClient;
+
+use crate::api::input::authorizer::Authorizer;
+use crate::api::input::permission_requirement::PermissionRequirement;
+use crate::api::input::request::Request;
+use crate::api::input::request_header::RequestHeader;
+use crate::api::input::revocation_list::RevocationList;
+use crate::api::input::token_validator;
+use crate::api::input::token_validator::TokenValidator;
+use crate::api::shutdown::ShutdownSignal;
+use crate::config::openid_connect_config::OpenIdConnectConfig;
+use crate::config::permission_mappings::PermissionMappings;
+use crate::error::Error;
+
+/// Default authorizer, which requires a valid and non revoked jwt
+/// containing the '{action}:{element}' permission within the requested tenant.
+pub struct JwtAuthorizer {
+    token_validator: TokenValidator,
+    revocation_list: Arc<RevocationList>,
+}
+
+impl JwtAuthorizer {
+    pub fn new(
+        token_validator: TokenValidator,
+        revocation_list: Arc<RevocationList>,
+    ) -> JwtAuthorizer {
+        JwtAuthorizer {
+            token_validator,
+            revocation_list,
+        }
+    }
+}
+
+#[async_trait]
+impl Authorizer for JwtAuthorizer {
+    async fn authorize(&self, mut request: Request) -> Result<Request, Error> {
+        let raw_token = request.try_get_token()?;
+
+        let token = self.token_validator.validate(raw_token.as_str()).await?;
+
+        self.revocation_list.check(&token)?;
+
+        let requirement = match &request.permission_requirement {
+            Some(requirement) => requirement.clone(),
+            None => {
+                let header = request.try_get_header()?;
+                PermissionRequirement::Single(permission_from_header(header))
+            }
+        };
+
+        requirement.check_in_tenant(&token, request.requested_tenant.as_deref())?;
+
+        request.authorized_token = Some(token);
+
+        Ok(request)
+    }
+
+    async fn run(&self, shutdown_signal: ShutdownSignal) -> Result<(), Error> {
+        self.token_validator.run(shutdown_signal).await
+    }
+}
+
+fn permission_from_header(header: RequestHeader) -> String {
+    format!("{}:{}", header.action(), header.element())
+}
+
+pub async fn try_generate_jwt_authorizer(
+    openid_connect: OpenIdConnectConfig,
+    permission_mappings: PermissionMappings,
+    revocation_list: Arc<RevocationList>,
+    state_tracker_client: StateTrackerClient,
+) -> Result<JwtAuthorizer, Error> {
+    let token_validator = token_validator::try_generate_token_validator(
+        openid_connect,
+        permission_mappings,
+        state_tracker_client,
+    )
+    .await?;
+
+    Ok(JwtAuthorizer::new(token_validator, revocation_list))
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
+
+use crate::api::input::authorizer::Authorizer;
+use crate::api::input::input_element::ActionAccess;
+use crate::api::input::middleware::{Middleware, Next};
+use crate::api::input::permission_requirement::PermissionRequirement;
+use crate::api::input::request::Request;
+use crate::api::tenant;
+use crate::error::{Error, ErrorKind};
+
+/// Authorizes requests depending on the access of their action, after setting their
+/// permission requirement and, for tenant isolated elements, their requested tenant.
+/// Authorized tokens must be members of the requested tenant, which only then becomes
+/// the request's tenant.
+pub struct AuthorizationMiddleware {
+    authorizer: Arc<dyn Authorizer>,
+    action_access: HashMap<&'static str, ActionAccess>,
+    permission_requirements: HashMap<&'static str, PermissionRequirement>,
+    tenant_isolated: bool,
+}
+
+impl AuthorizationMiddleware {
+    pub fn new(
+        authorizer: Arc<dyn Authorizer>,
+        action_access: HashMap<&'static str, ActionAccess>,
+        permission_requirements: HashMap<&'static str, PermissionRequirement>,
+        tenant_isolated: bool,
+    ) -> AuthorizationMiddleware {
+        AuthorizationMiddleware {
+            authorizer,
+            action_access,
+            permission_requirements,
+            tenant_isolated,
+        }
+    }
+
+    async fn authorize(&self, mut request: Request) -> Result<Request, Error> {
+        let header = request.try_get_header()?;
+
+        request.permission_requirement =
+            self.permission_requirements.get(header.action()).cloned();
+
+        if self.tenant_isolated {
+            request.requested_tenant = tenant::tenant_of(&request.data).map(str::to_string);
+
+            if request.requested_tenant.is_none() {
+                return Err(Error::new(
+                    ErrorKind::AuthorizationFailure,
+                    format!("request has no '{}'", tenant::TENANT_KEY),
+                ));
+            }
+        }
+
+        let access = self
+            .action_access
+            .get(header.action())
+            .copied()
+            .unwrap_or_default();
+
+        let request = match access {
+            ActionAccess::Authenticated => self.authorizer.authorize(request).await?,
+            ActionAccess::Public => request,
+            // Only requests without a token are anonymous, presented tokens which are
+            // invalid or revoked fail the authorization.
+            ActionAccess::OptionallyAuthenticated => {
+                if !header.has_token() {
+                    return Ok(request);
+                }
+
+                self.authorizer.authorize(request).await?
+            }
+        };
+
+        self.verify_tenant(request)
+    }
+
+    /// Enforced here rather than within the authorizer, so custom authorizers
+    /// cannot skip it.
+    fn verify_tenant(&self, mut request: Request) -> Result<Request, Error> {
+        let token = match &request.authorized_token {
+            Some(token) => token,
+            None => {
+                request.tenant = None;
+                return Ok(request);
+            }
+        };
+
+        if !self.tenant_isolated {
+            request.tenant = token.organization_id().ok().map(str::to_string);
+            return Ok(request);
+        }
+
+        let requested_tenant = request.requested_tenant.clone().unwrap_or_default();
+
+        if !token.is_member_of(requested_tenant.as_str()) {
+            return Err(Error::new(
+                ErrorKind::AuthorizationFailure,
+                format!("token is not a member of tenant '{}'", requested_tenant),
+            ));
+        }
+
+        request.tenant = Some(requested_tenant);
+
+        Ok(request)
+    }
+}
+
+#[async_trait]
+impl<LogicRequestType: Send + 'static> Middleware<LogicRequestType> for AuthorizationMiddleware {
+    async fn handle(
+        &self,
+        request: Request,
+        next: Next<LogicRequestType>,
+    ) -> Result<RequestResult, Error> {
+        let request = match self.authorize(request).await {
+            Ok(request) => request,
+            Err(error) => {
+                return Err(Error::new(
+                    error.kind(),
+                    format!("request authorization failure: {}", error),
+                ));
+            }
+        };
+
+        next.run(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{Header, TokenData};
+    use serde_json::{json, Map, Value};
+
+    use crate::api::input::token::Token;
+
+    use super::*;
+
+    /// Custom authorizer accepting any token, without checking the tenant.
+    struct AcceptingAuthorizer {
+        claims: Value,
+    }
+
+    #[async_trait]
+    impl Authorizer for AcceptingAuthorizer {
+        async fn authorize(&self, mut request: Request) -> Result<Request, Error> {
+            let claims = serde_json::from_value(self.claims.clone()).unwrap();
+            let token = Token::try_new(TokenData {
+                header: Header::default(),
+                claims,
+            })?;
+            request.authorized_token = Some(token);
+
+            Ok(request)
+        }
+    }
+
+    fn middleware(
+        claims: Value,
+        access: ActionAccess,
+        tenant_isolated: bool,
+    ) -> AuthorizationMiddleware {
+        AuthorizationMiddleware::new(
+            Arc::new(AcceptingAuthorizer { claims }),
+            HashMap::from([("read", access)]),
+            HashMap::new(),
+            tenant_isolated,
+        )
+    }
+
+    fn request(token: &str) -> Request {
+        let data: Map<String, Value> = serde_json::from_value(json!({
+            "header": { "token": token, "element": "items", "action": "read" },
+            "organization_id": "org1"
+        }))
+        .unwrap();
+
+        Request::new(data)
+    }
+
+    #[tokio::test]
+    async fn sets_tenant_of_member_tokens() {
+        let middleware = middleware(
+            json!({ "organization_id": "org1", "permissions": ["read:items"] }),
+            ActionAccess::Authenticated,
+            true,
+        );
+
+        let request = middleware.authorize(request("token")).await.unwrap();
+
+        assert_eq!(request.tenant.as_deref(), Some("org1"));
+    }
+
+    #[tokio::test]
+    async fn sets_tenant_of_tokens_with_tenant_scoped_grants() {
+        let middleware = middleware(
+            json!({ "organization_id": "org2", "permissions": ["read:items@org1"] }),
+            ActionAccess::Authenticated,
+            true,
+        );
+
+        let request = middleware.authorize(request("token")).await.unwrap();
+
+        assert_eq!(request.tenant.as_deref(), Some("org1"));
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_of_other_tenants_from_custom_authorizers() {
+        let middleware = middleware(
+            json!({ "organization_id": "org2", "permissions": ["read:items"] }),
+            ActionAccess::Authenticated,
+            true,
+        );
+
+        let error = middleware.authorize(request("token")).await.unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::AuthorizationFailure);
+    }
+
+    #[tokio::test]
+    async fn leaves_tenant_of_anonymous_requests_unset() {
+        for access in [ActionAccess::Public, ActionAccess::OptionallyAuthenticated] {
+            let middleware = middleware(json!({}), access, true);
+
+            let request = middleware.authorize(request("")).await.unwrap();
+
+            assert_eq!(request.tenant, None);
+            assert_eq!(request.requested_tenant.as_deref(), Some("org1"));
+        }
+    }
+
+    #[tokio::test]
+    async fn takes_tenant_of_other_elements_from_token() {
+        let middleware = middleware(
+            json!({ "organization_id": "org2", "permissions": ["read:items"] }),
+            ActionAccess::Authenticated,
+            false,
+        );
+
+        let request = middleware.authorize(request("token")).await.unwrap();
+
+        assert_eq!(request.tenant.as_deref(), Some("org2"));
+        assert_eq!(request.requested_tenant, None);
+    }
+}
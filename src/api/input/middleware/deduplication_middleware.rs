@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
+
+use crate::api::input::idempotency_store::{IdempotencyEntry, IdempotencyStore};
+use crate::api::input::middleware::{Middleware, Next};
+use crate::api::input::request::Request;
+use crate::error::{Error, ErrorKind};
+
+/// Handles requests only once per idempotency key, replaying the result of the original
+/// request to duplicates whenever it has already been handled. Duplicates of requests
+/// in progress are delivered again later on, once the original may have been handled.
+pub struct DeduplicationMiddleware {
+    element: String,
+    idempotency_store: Arc<dyn IdempotencyStore>,
+}
+
+impl DeduplicationMiddleware {
+    pub fn new(
+        element: String,
+        idempotency_store: Arc<dyn IdempotencyStore>,
+    ) -> DeduplicationMiddleware {
+        DeduplicationMiddleware {
+            element,
+            idempotency_store,
+        }
+    }
+
+    /// Keys are scoped to the element and the token's subject, so results are never
+    /// replayed to other users.
+    fn idempotency_key(&self, request: &Request) -> Option<String> {
+        let header_key = match request.try_get_header() {
+            Ok(header) => header.idempotency_key().map(str::to_string),
+            Err(_) => None,
+        };
+
+        let key = match header_key {
+            Some(key) => key,
+            None => request.message_id.clone()?,
+        };
+
+        let subject = match &request.authorized_token {
+            Some(token) => token.sub().unwrap_or_default(),
+            None => "",
+        };
+
+        Some(format!("{}:{}:{}", self.element, subject, key))
+    }
+
+    /// Succeeding results are kept for duplicates, failed requests are released for retries.
+    async fn settle(&self, idempotency_key: &str, result: &Result<RequestResult, Error>) {
+        let settled = match result {
+            Ok(request_result @ RequestResult::Ok(_)) => match serde_json::to_value(request_result) {
+                Ok(result) => self.idempotency_store.complete(idempotency_key, result).await,
+                Err(error) => {
+                    log::warn!("failed to serialize result for deduplication: {}", error);
+                    self.idempotency_store.release(idempotency_key).await
+                }
+            },
+            _ => self.idempotency_store.release(idempotency_key).await,
+        };
+
+        if let Err(error) = settled {
+            log::warn!("failed to settle idempotency key '{}': {}", idempotency_key, error);
+        }
+    }
+}
+
+#[async_trait]
+impl<LogicRequestType: Send + 'static> Middleware<LogicRequestType> for DeduplicationMiddleware {
+    async fn handle(
+        &self,
+        request: Request,
+        next: Next<LogicRequestType>,
+    ) -> Result<RequestResult, Error> {
+        let idempotency_key = match self.idempotency_key(&request) {
+            Some(idempotency_key) => idempotency_key,
+            None => return next.run(request).await,
+        };
+
+        match self.idempotency_store.try_claim(idempotency_key.as_str()).await {
+            Ok(None) => (),
+            Ok(Some(IdempotencyEntry::InProgress)) => {
+                return Err(Error::new(
+                    ErrorKind::DuplicateRequest,
+                    format!("duplicate of a request in progress: {}", idempotency_key),
+                ));
+            }
+            Ok(Some(IdempotencyEntry::Completed(result))) => {
+                log::info!("replaying result of a duplicate request: {}", idempotency_key);
+
+                return match serde_json::from_value::<RequestResult>(result) {
+                    Ok(result) => Ok(result),
+                    Err(error) => Err(Error::new(
+                        ErrorKind::InternalFailure,
+                        format!("failed to deserialize replayed result: {}", error),
+                    )),
+                };
+            }
+            Err(error) => {
+                log::warn!("failed to deduplicate request: {}", error);
+                return next.run(request).await;
+            }
+        }
+
+        let result = next.run(request).await;
+
+        self.settle(idempotency_key.as_str(), &result).await;
+
+        result
+    }
+}
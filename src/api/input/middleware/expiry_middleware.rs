@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
+
+use crate::api::input::deadline;
+use crate::api::input::middleware::{Middleware, Next};
+use crate::api::input::request::Request;
+use crate::error::{Error, ErrorKind};
+
+/// Narrows the deadline of the request down with the header's 'deadline' and the
+/// timeout of its action, then stops the request if its deadline has already been
+/// reached. Their deliveries are dropped without any reply.
+#[derive(Default)]
+pub struct ExpiryMiddleware {
+    timeouts: HashMap<&'static str, Duration>,
+}
+
+impl ExpiryMiddleware {
+    /// Timeouts of the actions, whose handlers are never cancelled otherwise.
+    pub fn new(timeouts: HashMap<&'static str, Duration>) -> ExpiryMiddleware {
+        ExpiryMiddleware { timeouts }
+    }
+}
+
+#[async_trait]
+impl<LogicRequestType: Send + 'static> Middleware<LogicRequestType> for ExpiryMiddleware {
+    async fn handle(
+        &self,
+        mut request: Request,
+        next: Next<LogicRequestType>,
+    ) -> Result<RequestResult, Error> {
+        if let Ok(header) = request.try_get_header() {
+            request.deadline = deadline::deadline_of(
+                &header,
+                request.deadline,
+                self.timeouts.get(header.action()).copied(),
+            );
+        }
+
+        if deadline::has_expired(request.deadline) {
+            return Err(Error::new(ErrorKind::ExpiredRequest, "request has expired"));
+        }
+
+        next.run(request).await
+    }
+}
@@ -0,0 +1,89 @@
+pub mod authorization_middleware;
+pub mod deduplication_middleware;
+pub mod expiry_middleware;
+pub mod parsing_middleware;
+pub mod policy_middleware;
+pub mod sanitization_middleware;
+pub mod timeout_middleware;
+
+use std::sync::Arc;
+
+use async_channel::Sender;
+use async_trait::async_trait;
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
+
+use crate::api::input::input_element::RequestHandler;
+use crate::api::input::request::Request;
+use crate::error::Error;
+
+/// Stage wrapping the request handler, which may change the request before passing
+/// it to the next stage, change the result it returns or return without calling it.
+///
+/// Errors stop the request, whether before it is handled, once it has timed out or
+/// once the handler has failed through [retryable] or [permanent], and are disposed
+/// depending on their failure class, whereas results are those of handled requests.
+///
+/// [retryable]: crate::api::input::retry_policy::retryable
+/// [permanent]: crate::api::input::retry_policy::permanent
+#[async_trait]
+pub trait Middleware<LogicRequestType>: Send + Sync {
+    async fn handle(
+        &self,
+        request: Request,
+        next: Next<LogicRequestType>,
+    ) -> Result<RequestResult, Error>;
+}
+
+/// Middlewares wrapping a request handler, run in the order they have been given.
+pub struct Pipeline<LogicRequestType> {
+    middlewares: Arc<[Arc<dyn Middleware<LogicRequestType>>]>,
+    request_handler: RequestHandler<LogicRequestType>,
+}
+
+impl<LogicRequestType: Send + 'static> Pipeline<LogicRequestType> {
+    pub fn new(
+        middlewares: Vec<Arc<dyn Middleware<LogicRequestType>>>,
+        request_handler: RequestHandler<LogicRequestType>,
+    ) -> Pipeline<LogicRequestType> {
+        Pipeline {
+            middlewares: middlewares.into(),
+            request_handler,
+        }
+    }
+
+    pub async fn run(
+        &self,
+        request: Request,
+        logic_request_sender: Sender<LogicRequestType>,
+    ) -> Result<RequestResult, Error> {
+        let next = Next {
+            middlewares: self.middlewares.clone(),
+            position: 0,
+            request_handler: self.request_handler.clone(),
+            logic_request_sender,
+        };
+
+        next.run(request).await
+    }
+}
+
+/// Remaining stages of a pipeline, the last one being the request handler.
+pub struct Next<LogicRequestType> {
+    middlewares: Arc<[Arc<dyn Middleware<LogicRequestType>>]>,
+    position: usize,
+    request_handler: RequestHandler<LogicRequestType>,
+    logic_request_sender: Sender<LogicRequestType>,
+}
+
+impl<LogicRequestType: Send + 'static> Next<LogicRequestType> {
+    pub async fn run(mut self, request: Request) -> Result<RequestResult, Error> {
+        let middleware = match self.middlewares.get(self.position) {
+            Some(middleware) => middleware.clone(),
+            None => return (self.request_handler)(request, self.logic_request_sender).await,
+        };
+
+        self.position += 1;
+
+        middleware.handle(request, self).await
+    }
+}
@@ -0,0 +1,45 @@
+use async_trait::async_trait;
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
+use serde_json::{Map, Value};
+
+use crate::api::input::middleware::{Middleware, Next};
+use crate::api::input::request::Request;
+use crate::error::{Error, ErrorKind};
+
+/// Parses the payload of the request into its data, stopping requests which are not
+/// a json object encoded as utf8.
+#[derive(Default)]
+pub struct ParsingMiddleware;
+
+#[async_trait]
+impl<LogicRequestType: Send + 'static> Middleware<LogicRequestType> for ParsingMiddleware {
+    async fn handle(
+        &self,
+        mut request: Request,
+        next: Next<LogicRequestType>,
+    ) -> Result<RequestResult, Error> {
+        request.data = try_parse(request.payload.as_slice())?;
+
+        next.run(request).await
+    }
+}
+
+fn try_parse(payload: &[u8]) -> Result<Map<String, Value>, Error> {
+    let request_data = match std::str::from_utf8(payload) {
+        Ok(request_data) => request_data,
+        Err(error) => {
+            return Err(Error::new(
+                ErrorKind::MalformedRequest,
+                format!("delivery is not an utf8 string: {}", error),
+            ));
+        }
+    };
+
+    match serde_json::from_str::<Map<String, Value>>(request_data) {
+        Ok(data) => Ok(data),
+        Err(error) => Err(Error::new(
+            ErrorKind::MalformedRequest,
+            format!("delivery is not a json object: {}", error),
+        )),
+    }
+}
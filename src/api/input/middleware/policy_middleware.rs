@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
+
+use crate::api::input::middleware::{Middleware, Next};
+use crate::api::input::policy::Policy;
+use crate::api::input::request::Request;
+use crate::error::Error;
+
+/// Evaluates the policies in order, stopping the request on the first violation.
+pub struct PolicyMiddleware {
+    policies: Vec<Arc<dyn Policy>>,
+}
+
+impl PolicyMiddleware {
+    pub fn new(policies: Vec<Arc<dyn Policy>>) -> PolicyMiddleware {
+        PolicyMiddleware { policies }
+    }
+}
+
+#[async_trait]
+impl<LogicRequestType: Send + 'static> Middleware<LogicRequestType> for PolicyMiddleware {
+    async fn handle(
+        &self,
+        request: Request,
+        next: Next<LogicRequestType>,
+    ) -> Result<RequestResult, Error> {
+        for policy in self.policies.iter() {
+            if let Err(violation) = policy.evaluate(&request).await {
+                return Err(violation.into());
+            }
+        }
+
+        next.run(request).await
+    }
+}
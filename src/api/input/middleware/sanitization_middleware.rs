@@ -0,0 +1,36 @@
+use async_trait::async_trait;
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
+
+use crate::api::input::middleware::{Middleware, Next};
+use crate::api::input::request::Request;
+use crate::api::input::sanitizer::validate_action;
+use crate::error::{Error, ErrorKind};
+
+/// Stops requests whose action is not one of the element's actions.
+pub struct SanitizationMiddleware {
+    actions: &'static [&'static str],
+}
+
+impl SanitizationMiddleware {
+    pub fn new(actions: &'static [&'static str]) -> SanitizationMiddleware {
+        SanitizationMiddleware { actions }
+    }
+}
+
+#[async_trait]
+impl<LogicRequestType: Send + 'static> Middleware<LogicRequestType> for SanitizationMiddleware {
+    async fn handle(
+        &self,
+        request: Request,
+        next: Next<LogicRequestType>,
+    ) -> Result<RequestResult, Error> {
+        if let Err(error) = validate_action(&request, self.actions) {
+            return Err(Error::new(
+                ErrorKind::MalformedRequest,
+                format!("request sanitization failure: {}", error),
+            ));
+        }
+
+        next.run(request).await
+    }
+}
@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
+
+use crate::api::input::middleware::{Middleware, Next};
+use crate::api::input::request::Request;
+use crate::error::{Error, ErrorKind};
+
+/// Cancels the following stages by dropping them once the request's deadline is reached,
+/// the request failing as timed out.
+#[derive(Default)]
+pub struct TimeoutMiddleware;
+
+#[async_trait]
+impl<LogicRequestType: Send + 'static> Middleware<LogicRequestType> for TimeoutMiddleware {
+    async fn handle(
+        &self,
+        request: Request,
+        next: Next<LogicRequestType>,
+    ) -> Result<RequestResult, Error> {
+        let deadline = match request.deadline {
+            Some(deadline) => deadline,
+            None => return next.run(request).await,
+        };
+
+        match tokio::time::timeout_at(deadline, next.run(request)).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::new(
+                ErrorKind::TimedOutRequest,
+                "request handling timed out",
+            )),
+        }
+    }
+}
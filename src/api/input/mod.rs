@@ -1,10 +1,23 @@
 pub mod amqp_request_dispatch;
 pub mod amqp_request_replier;
 pub mod authorizer;
+pub mod authorizer_chain;
+pub mod key_set;
 pub mod request;
 pub mod request_header;
 pub mod request_result_error_extension;
+pub mod revocation_consumer;
+pub mod revocation_list;
 pub mod sanitizer;
 pub mod token;
 pub mod token_validator;
 pub mod input_element;
+pub mod jwt_authorizer;
+pub mod permission_requirement;
+pub mod permission_set;
+pub mod policy;
+pub mod disposition;
+pub mod retry_policy;
+pub mod idempotency_store;
+pub mod deadline;
+pub mod middleware;
@@ -0,0 +1,113 @@
+use crate::api::input::token::Token;
+use crate::error::{Error, ErrorKind};
+
+/// Permissions a token must have in order to perform an action.
+/// Requirements without any permission are never met.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PermissionRequirement {
+    Single(String),
+    AnyOf(Vec<String>),
+    AllOf(Vec<String>),
+}
+
+impl PermissionRequirement {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            PermissionRequirement::Single(_) => false,
+            PermissionRequirement::AnyOf(permissions) | PermissionRequirement::AllOf(permissions) => {
+                permissions.is_empty()
+            }
+        }
+    }
+
+    pub fn check(&self, token: &Token) -> Result<(), Error> {
+        self.check_in_tenant(token, None)
+    }
+
+    /// See [Token::has_permission_in_tenant].
+    pub fn check_in_tenant(&self, token: &Token, tenant: Option<&str>) -> Result<(), Error> {
+        if self.is_empty() {
+            return Err(Error::new(
+                ErrorKind::PermissionNotFound,
+                "permission requirement has no permissions",
+            ));
+        }
+
+        match self {
+            PermissionRequirement::Single(permission) => {
+                token.has_permission_in_tenant(permission, tenant)
+            }
+            PermissionRequirement::AnyOf(permissions) => {
+                if permissions
+                    .iter()
+                    .any(|permission| token.has_permission_in_tenant(permission, tenant).is_ok())
+                {
+                    return Ok(());
+                }
+
+                Err(Error::new(
+                    ErrorKind::PermissionNotFound,
+                    format!("none of the permissions {:?} could be found", permissions),
+                ))
+            }
+            PermissionRequirement::AllOf(permissions) => {
+                for permission in permissions {
+                    token.has_permission_in_tenant(permission, tenant)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use jsonwebtoken::{Header, TokenData};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn token(permissions: &[&str]) -> Token {
+        let claims: HashMap<String, Value> =
+            serde_json::from_value(json!({ "permissions": permissions })).unwrap();
+
+        Token::try_new(TokenData {
+            header: Header::default(),
+            claims,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn fails_closed_for_empty_requirements() {
+        let token = token(&["*:*"]);
+
+        assert!(PermissionRequirement::AllOf(Vec::new()).check(&token).is_err());
+        assert!(PermissionRequirement::AnyOf(Vec::new()).check(&token).is_err());
+    }
+
+    #[test]
+    fn requires_any_permission() {
+        let requirement = PermissionRequirement::AnyOf(vec![
+            "read:items".to_string(),
+            "read:orders".to_string(),
+        ]);
+
+        assert!(requirement.check(&token(&["read:orders"])).is_ok());
+        assert!(requirement.check(&token(&["write:items"])).is_err());
+    }
+
+    #[test]
+    fn requires_every_permission() {
+        let requirement = PermissionRequirement::AllOf(vec![
+            "read:items".to_string(),
+            "read:orders".to_string(),
+        ]);
+
+        assert!(requirement.check(&token(&["read:items", "read:orders"])).is_ok());
+        assert!(requirement.check(&token(&["read:items"])).is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+const WILDCARD: &str = "*";
+const ACTION_SEPARATOR: char = ':';
+const ELEMENT_SEPARATOR: char = '/';
+const TENANT_SEPARATOR: char = '@';
+
+/// Permissions granted to a token, following the 'action:element' format.
+///
+/// A granted permission covers a required one whenever:
+/// * its action is the same or '*'.
+/// * its element is the same or a parent of the required element, elements being
+///   hierarchical paths separated by '/'. Each '*' segment matches any single segment.
+///
+/// Hence 'read:inventory' covers 'read:inventory/items', 'read:*' covers every
+/// element for 'read' and '*:*' covers everything. There are no deny rules, so a
+/// single covering grant is enough regardless of the order of the grants.
+///
+/// Grants suffixed by '@{tenant}', e.g. 'read:items@org123', are tenant-scoped
+/// and only cover permissions required within that tenant.
+#[derive(Debug, Clone, Default)]
+pub struct PermissionSet {
+    elements_per_action: HashMap<String, ElementNode>,
+    permissions_per_tenant: HashMap<String, PermissionSet>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ElementNode {
+    /// Granted up to this segment, so every element below is covered too.
+    granted: bool,
+    children: HashMap<String, ElementNode>,
+    wildcard: Option<Box<ElementNode>>,
+}
+
+impl PermissionSet {
+    pub fn new() -> PermissionSet {
+        PermissionSet::default()
+    }
+
+    pub fn from_permissions(permissions: &[String]) -> PermissionSet {
+        let mut permission_set = PermissionSet::new();
+
+        for permission in permissions {
+            permission_set.insert(permission.as_str());
+        }
+
+        permission_set
+    }
+
+    /// Permissions not following the 'action:element' format are ignored.
+    pub fn insert(&mut self, permission: &str) {
+        match permission.rsplit_once(TENANT_SEPARATOR) {
+            Some((permission, tenant)) => self
+                .permissions_per_tenant
+                .entry(tenant.to_string())
+                .or_default()
+                .insert_unscoped(permission),
+            None => self.insert_unscoped(permission),
+        }
+    }
+
+    /// Only the last '@' separates the tenant, so the permission itself is not split again.
+    fn insert_unscoped(&mut self, permission: &str) {
+        let (action, element) = match permission.split_once(ACTION_SEPARATOR) {
+            Some(action_and_element) => action_and_element,
+            None => {
+                log::debug!("ignoring malformed permission '{}'", permission);
+                return;
+            }
+        };
+
+        let mut node = self
+            .elements_per_action
+            .entry(action.to_string())
+            .or_default();
+
+        for segment in element.split(ELEMENT_SEPARATOR) {
+            node = if segment == WILDCARD {
+                node.wildcard.get_or_insert_with(Box::default)
+            } else {
+                node.children.entry(segment.to_string()).or_default()
+            };
+        }
+
+        node.granted = true;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements_per_action.is_empty() && self.permissions_per_tenant.is_empty()
+    }
+
+    /// Whether any grant is scoped to the tenant.
+    pub fn has_tenant(&self, tenant: &str) -> bool {
+        self.permissions_per_tenant.contains_key(tenant)
+    }
+
+    /// Whether a permission is granted through a grant scoped to the tenant.
+    pub fn contains_in_tenant(&self, permission: &str, tenant: &str) -> bool {
+        match self.permissions_per_tenant.get(tenant) {
+            Some(permission_set) => permission_set.contains(permission),
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, permission: &str) -> bool {
+        let (action, element) = match permission.split_once(ACTION_SEPARATOR) {
+            Some(action_and_element) => action_and_element,
+            None => return false,
+        };
+
+        let segments: Vec<&str> = element.split(ELEMENT_SEPARATOR).collect();
+
+        [action, WILDCARD].iter().any(|action| {
+            match self.elements_per_action.get(*action) {
+                Some(node) => node.covers(&segments),
+                None => false,
+            }
+        })
+    }
+}
+
+impl ElementNode {
+    fn covers(&self, segments: &[&str]) -> bool {
+        if self.granted {
+            return true;
+        }
+
+        let (segment, remaining_segments) = match segments.split_first() {
+            Some(split) => split,
+            None => return false,
+        };
+
+        if let Some(child) = self.children.get(*segment) {
+            if child.covers(remaining_segments) {
+                return true;
+            }
+        }
+
+        match &self.wildcard {
+            Some(wildcard) => wildcard.covers(remaining_segments),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permission_set(permissions: &[&str]) -> PermissionSet {
+        let permissions: Vec<String> = permissions
+            .iter()
+            .map(|permission| permission.to_string())
+            .collect();
+
+        PermissionSet::from_permissions(&permissions)
+    }
+
+    #[test]
+    fn matches_exact_permission() {
+        let permission_set = permission_set(&["read:items"]);
+
+        assert!(permission_set.contains("read:items"));
+        assert!(!permission_set.contains("write:items"));
+        assert!(!permission_set.contains("read:orders"));
+        assert!(!permission_set.contains("read:item"));
+        assert!(!permission_set.contains("read:items_archive"));
+    }
+
+    #[test]
+    fn covers_child_elements() {
+        let permission_set = permission_set(&["read:inventory"]);
+
+        assert!(permission_set.contains("read:inventory/items"));
+        assert!(permission_set.contains("read:inventory/items/42"));
+        assert!(!permission_set.contains("read:warehouse/inventory"));
+    }
+
+    #[test]
+    fn does_not_cover_parent_elements() {
+        let permission_set = permission_set(&["read:inventory/items"]);
+
+        assert!(permission_set.contains("read:inventory/items"));
+        assert!(!permission_set.contains("read:inventory"));
+        assert!(!permission_set.contains("read:inventory/orders"));
+    }
+
+    #[test]
+    fn matches_wildcard_action() {
+        let permission_set = permission_set(&["*:items"]);
+
+        assert!(permission_set.contains("read:items"));
+        assert!(permission_set.contains("write:items/42"));
+        assert!(!permission_set.contains("read:orders"));
+    }
+
+    #[test]
+    fn matches_wildcard_element() {
+        let permission_set = permission_set(&["read:*"]);
+
+        assert!(permission_set.contains("read:items"));
+        assert!(permission_set.contains("read:orders/42"));
+        assert!(!permission_set.contains("write:items"));
+    }
+
+    #[test]
+    fn matches_wildcard_segment_as_a_single_segment() {
+        let permission_set = permission_set(&["read:inventory/*/price"]);
+
+        assert!(permission_set.contains("read:inventory/items/price"));
+        assert!(permission_set.contains("read:inventory/orders/price/history"));
+        assert!(!permission_set.contains("read:inventory/items"));
+        assert!(!permission_set.contains("read:inventory/items/stock"));
+        assert!(!permission_set.contains("read:inventory/items/42/price"));
+    }
+
+    #[test]
+    fn matches_wildcard_action_and_element() {
+        let permission_set = permission_set(&["*:*"]);
+
+        assert!(permission_set.contains("read:items"));
+        assert!(permission_set.contains("delete:inventory/items/42"));
+    }
+
+    #[test]
+    fn prefers_exact_segment_before_wildcard() {
+        let permission_set = permission_set(&["read:inventory/items/price", "read:inventory/*"]);
+
+        assert!(permission_set.contains("read:inventory/items/stock"));
+        assert!(permission_set.contains("read:inventory/orders"));
+    }
+
+    #[test]
+    fn ignores_order_of_grants() {
+        let permission_set = permission_set(&["read:inventory/items", "read:inventory"]);
+
+        assert!(permission_set.contains("read:inventory/orders"));
+    }
+
+    #[test]
+    fn ignores_malformed_permissions() {
+        let permission_set = permission_set(&["read", ""]);
+
+        assert!(permission_set.is_empty());
+        assert!(!permission_set.contains("read"));
+        assert!(!permission_set.contains("read:items"));
+    }
+
+    #[test]
+    fn rejects_malformed_required_permission() {
+        let permission_set = permission_set(&["*:*"]);
+
+        assert!(!permission_set.contains("read"));
+    }
+
+    #[test]
+    fn scopes_grants_to_tenant() {
+        let permission_set = permission_set(&["read:items@org1"]);
+
+        assert!(!permission_set.is_empty());
+        assert!(!permission_set.contains("read:items"));
+        assert!(permission_set.contains_in_tenant("read:items", "org1"));
+        assert!(permission_set.contains_in_tenant("read:items/42", "org1"));
+        assert!(!permission_set.contains_in_tenant("read:items", "org2"));
+        assert!(!permission_set.contains_in_tenant("write:items", "org1"));
+    }
+
+    #[test]
+    fn splits_tenant_at_last_separator() {
+        let permission_set = permission_set(&["read:users/a@b@org1"]);
+
+        assert!(permission_set.contains_in_tenant("read:users/a@b", "org1"));
+        assert!(!permission_set.contains_in_tenant("read:users/a", "b@org1"));
+    }
+
+    #[test]
+    fn matches_wildcards_within_tenant() {
+        let permission_set = permission_set(&["*:inventory@org1"]);
+
+        assert!(permission_set.contains_in_tenant("write:inventory/items", "org1"));
+        assert!(!permission_set.contains_in_tenant("write:orders", "org1"));
+    }
+
+    #[test]
+    fn keeps_unscoped_grants_out_of_tenants() {
+        let permission_set = permission_set(&["read:items"]);
+
+        assert!(!permission_set.contains_in_tenant("read:items", "org1"));
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::api::input::request::Request;
+use crate::error::{Error, ErrorKind};
+
+/// Reason why a policy rejected a request.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PolicyViolation {
+    pub policy: String,
+    pub reason: String,
+}
+
+impl PolicyViolation {
+    pub fn new(policy: impl Into<String>, reason: impl Into<String>) -> PolicyViolation {
+        PolicyViolation {
+            policy: policy.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "policy '{}' violated: {}", self.policy, self.reason)
+    }
+}
+
+impl From<PolicyViolation> for Error {
+    fn from(violation: PolicyViolation) -> Self {
+        Error::with_violation(ErrorKind::AuthorizationFailure, violation.to_string(), violation)
+    }
+}
+
+/// Attribute based rule evaluated after the request has been authorized and before
+/// it is handled, with access to both the request's data and its authorized token.
+#[async_trait]
+pub trait Policy: Send + Sync {
+    async fn evaluate(&self, request: &Request) -> Result<(), PolicyViolation>;
+}
+
+/// Requires a request parameter to be equal to a claim of the authorized token,
+/// e.g. the request's 'organization_id' must match the token's 'organization_id'.
+pub struct ClaimMatchesParameterPolicy {
+    claim: String,
+    parameter: String,
+}
+
+impl ClaimMatchesParameterPolicy {
+    pub fn new(claim: impl Into<String>, parameter: impl Into<String>) -> ClaimMatchesParameterPolicy {
+        ClaimMatchesParameterPolicy {
+            claim: claim.into(),
+            parameter: parameter.into(),
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("claim '{}' matches parameter '{}'", self.claim, self.parameter)
+    }
+}
+
+#[async_trait]
+impl Policy for ClaimMatchesParameterPolicy {
+    async fn evaluate(&self, request: &Request) -> Result<(), PolicyViolation> {
+        let token = match &request.authorized_token {
+            Some(token) => token,
+            None => return Err(PolicyViolation::new(self.name(), "request has no authorized token")),
+        };
+
+        let claim = match token.get(self.claim.as_str()) {
+            Some(claim) => claim,
+            None => {
+                return Err(PolicyViolation::new(
+                    self.name(),
+                    format!("token has no '{}' claim", self.claim),
+                ))
+            }
+        };
+
+        match request.data.get(self.parameter.as_str()) {
+            Some(parameter) if parameter == claim => Ok(()),
+            Some(Value::Null) | None => Err(PolicyViolation::new(
+                self.name(),
+                format!("request has no '{}'", self.parameter),
+            )),
+            Some(_) => Err(PolicyViolation::new(
+                self.name(),
+                format!("'{}' does not match the token's '{}'", self.parameter, self.claim),
+            )),
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::api::input::permission_requirement::PermissionRequirement;
 use crate::api::input::request_header::RequestHeader;
 use crate::api::input::token::Token;
 use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
@@ -6,13 +7,30 @@ use cooplan_amqp_api_shared::api::input::request_result_error::{
 };
 use serde::de::DeserializeOwned;
 use serde_json::{Map, Value};
+use tokio::time::Instant;
 
 use crate::error::{Error, ErrorKind};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Request {
+    /// Body of the delivery carrying the request, parsed into its data by the pipeline.
+    pub payload: Vec<u8>,
     pub data: Map<String, Value>,
     pub authorized_token: Option<Token>,
+    /// Set by the input element whenever its action does not require the
+    /// default '{action}:{element}' permission.
+    pub permission_requirement: Option<PermissionRequirement>,
+    /// Organization the request acts upon. Only set once the request has an authorized
+    /// token: verified against the token for tenant isolated elements, otherwise taken from it.
+    pub tenant: Option<String>,
+    /// Organization stated by the request of a tenant isolated element, which is not
+    /// verified yet while authorizing.
+    pub requested_tenant: Option<String>,
+    /// Set from the delivery's 'expiration', then narrowed down by the pipeline with
+    /// the header's 'deadline' and the handler's timeout.
+    pub deadline: Option<Instant>,
+    /// Message id of the delivery carrying the request.
+    pub message_id: Option<String>,
 }
 
 const HEADER_KEY: &str = "header";
@@ -20,14 +38,35 @@ const HEADER_KEY: &str = "header";
 impl Request {
     pub fn new(request: Map<String, Value>) -> Request {
         Request {
+            payload: Vec::new(),
             data: request,
             authorized_token: None,
+            permission_requirement: None,
+            tenant: None,
+            requested_tenant: None,
+            deadline: None,
+            message_id: None,
         }
     }
 
+    /// Request whose data is still to be parsed from the payload.
+    pub fn from_payload(payload: Vec<u8>) -> Request {
+        let mut request = Request::new(Map::new());
+        request.payload = payload;
+
+        request
+    }
+
     pub fn try_get_token(&self) -> Result<String, Error> {
         let header = self.try_get_header()?;
 
+        if !header.has_token() {
+            return Err(Error::new(
+                ErrorKind::MalformedRequest,
+                "request header has no token",
+            ));
+        }
+
         Ok(header.token().to_string())
     }
 
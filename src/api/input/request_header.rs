@@ -2,9 +2,17 @@ use serde::Deserialize;
 
 #[derive(Deserialize)]
 pub struct RequestHeader {
+    /// Empty whenever the request carries no token, e.g. for public actions.
+    #[serde(default)]
     token: String,
     element: String,
     action: String,
+    /// Identifies retries of the same request, see [crate::api::input::idempotency_store].
+    #[serde(default)]
+    idempotency_key: Option<String>,
+    /// Unix timestamp in milliseconds after which the request is not worth handling.
+    #[serde(default)]
+    deadline: Option<u64>,
 }
 
 impl RequestHeader {
@@ -12,6 +20,10 @@ impl RequestHeader {
         self.token.as_str()
     }
 
+    pub fn has_token(&self) -> bool {
+        !self.token.is_empty()
+    }
+
     pub fn element(&self) -> &str {
         self.element.as_str()
     }
@@ -19,4 +31,12 @@ impl RequestHeader {
     pub fn action(&self) -> &str {
         self.action.as_str()
     }
+
+    pub fn idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+
+    pub fn deadline(&self) -> Option<u64> {
+        self.deadline
+    }
 }
@@ -1,7 +1,12 @@
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
 use cooplan_amqp_api_shared::api::input::request_result_error::{RequestResultError, RequestResultErrorKind};
+use serde_json::Value;
 
 use crate::error::{Error, ErrorKind};
 
+pub const POLICY_KEY: &str = "policy";
+pub const REASON_KEY: &str = "reason";
+
 impl From<ErrorKind> for RequestResultErrorKind {
     fn from(kind: ErrorKind) -> Self {
         match kind {
@@ -18,3 +23,53 @@ impl From<Error> for RequestResultError {
         RequestResultError::new(kind, error.message)
     }
 }
+
+/// Failed result of the error, whose policy violation is kept as separate
+/// 'policy' and 'reason' fields next to the kind and the message.
+pub fn error_result_of(error: Error) -> Result<Value, serde_json::Error> {
+    let violation = error.violation().cloned();
+    let mut result = serde_json::to_value(RequestResult::Err(error.into()))?;
+
+    if let (Some(violation), Some(Value::Object(error))) = (violation, result.get_mut("Err")) {
+        error.insert(POLICY_KEY.to_string(), Value::String(violation.policy));
+        error.insert(REASON_KEY.to_string(), Value::String(violation.reason));
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::api::input::policy::PolicyViolation;
+
+    use super::*;
+
+    #[test]
+    fn keeps_policy_and_reason_of_violations() {
+        let error: Error = PolicyViolation::new("business_hours", "outside of business hours").into();
+
+        assert_eq!(
+            error_result_of(error).unwrap(),
+            json!({
+                "Err": {
+                    "kind": "InternalFailure",
+                    "message": "policy 'business_hours' violated: outside of business hours",
+                    "policy": "business_hours",
+                    "reason": "outside of business hours"
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn omits_policy_of_other_errors() {
+        let error = Error::new(ErrorKind::MalformedRequest, "missing field");
+
+        assert_eq!(
+            error_result_of(error).unwrap(),
+            json!({ "Err": { "kind": "MalformedRequest", "message": "missing field" } })
+        );
+    }
+}
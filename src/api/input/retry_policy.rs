@@ -0,0 +1,96 @@
+use cooplan_amqp_api_shared::api::input::request_result::RequestResult;
+use lapin::message::Delivery;
+use lapin::types::AMQPValue;
+
+use crate::api::input::disposition::Disposition;
+use crate::error::{Error, ErrorKind};
+
+pub const ATTEMPT_HEADER: &str = "x-attempt";
+
+/// Transient failures are retried by publishing the delivery into a delay queue per
+/// attempt, from which it is dead-lettered back into the element's queue once its
+/// per-message TTL expires. After the last attempt it is dead-lettered.
+///
+/// Handlers mark their errors as retryable through [retryable] and as permanent
+/// through [permanent], permanent failures being dead-lettered without any retry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_delay_in_milliseconds: u64,
+    max_delay_in_milliseconds: u64,
+    dead_letter: Disposition,
+}
+
+impl RetryPolicy {
+    pub fn new(
+        max_attempts: u32,
+        initial_delay_in_milliseconds: u64,
+        max_delay_in_milliseconds: u64,
+        dead_letter_exchange: String,
+        dead_letter_routing_key: Option<String>,
+    ) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_delay_in_milliseconds,
+            max_delay_in_milliseconds,
+            dead_letter: Disposition::DeadLetter {
+                exchange: dead_letter_exchange,
+                routing_key: dead_letter_routing_key,
+            },
+        }
+    }
+
+    /// Including the first delivery.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    pub fn dead_letter(&self) -> &Disposition {
+        &self.dead_letter
+    }
+
+    /// Delay before the attempt following the given one, doubling after every attempt.
+    pub fn delay_in_milliseconds(&self, attempt: u32) -> u64 {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+
+        std::cmp::min(
+            self.initial_delay_in_milliseconds.saturating_mul(factor),
+            self.max_delay_in_milliseconds,
+        )
+    }
+}
+
+/// Queue holding the deliveries waiting for the attempt following the given one.
+pub fn delay_queue_name(queue: &str, attempt: u32) -> String {
+    format!("{}.retry.{}", queue, attempt)
+}
+
+/// Queue holding the duplicates of requests in progress, waiting for the original.
+pub fn postponement_queue_name(queue: &str) -> String {
+    format!("{}.postponed", queue)
+}
+
+/// Attempt of the delivery, starting at 1 for its first delivery.
+pub fn attempt_of(delivery: &Delivery) -> u32 {
+    let headers = match delivery.properties.headers() {
+        Some(headers) => headers,
+        None => return 1,
+    };
+
+    match headers.inner().get(ATTEMPT_HEADER) {
+        Some(AMQPValue::LongUInt(attempt)) => *attempt,
+        Some(AMQPValue::LongInt(attempt)) => (*attempt).max(1) as u32,
+        Some(AMQPValue::LongLongInt(attempt)) => (*attempt).clamp(1, u32::MAX as i64) as u32,
+        _ => 1,
+    }
+}
+
+/// Replied as an internal failure and retried whenever the element has a retry policy.
+pub fn retryable(message: impl Into<String>) -> Result<RequestResult, Error> {
+    Err(Error::new(ErrorKind::RetryableFailure, message))
+}
+
+/// Replied as an internal failure and dead-lettered without any retry.
+pub fn permanent(message: impl Into<String>) -> Result<RequestResult, Error> {
+    Err(Error::new(ErrorKind::PermanentFailure, message))
+}
@@ -0,0 +1,404 @@
+use std::sync::Arc;
+
+use cooplan_state_tracker::state::State;
+use cooplan_state_tracker::state_tracker_client::StateTrackerClient;
+use futures_util::TryStreamExt;
+use lapin::message::Delivery;
+use lapin::options::{
+    BasicCancelOptions, ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
+};
+use lapin::types::{AMQPValue, FieldTable, ShortString};
+use lapin::{Channel, Consumer, ExchangeKind};
+use uuid::Uuid;
+
+use crate::api::connection_supervisor::ConnectionSupervisor;
+use crate::api::input::revocation_list::{Revocation, RevocationList};
+use crate::api::shutdown;
+use crate::api::shutdown::ShutdownSignal;
+use crate::config::revocation_config::RevocationConfig;
+use crate::error::{Error, ErrorKind};
+
+const QUEUE_EXPIRY_ARGUMENT: &str = "x-expires";
+
+/// Consumes the revocations of its own instance's queue, filling the revocation list.
+pub struct RevocationConsumer {
+    channel: Arc<Channel>,
+    connection_supervisor: Arc<ConnectionSupervisor>,
+    consumer: Option<Consumer>,
+    queue_name: String,
+    config: RevocationConfig,
+    revocation_list: Arc<RevocationList>,
+    state_tracker_client: StateTrackerClient,
+}
+
+impl RevocationConsumer {
+    pub fn new(
+        channel: Arc<Channel>,
+        connection_supervisor: Arc<ConnectionSupervisor>,
+        config: RevocationConfig,
+        revocation_list: Arc<RevocationList>,
+        mut state_tracker_client: StateTrackerClient,
+    ) -> RevocationConsumer {
+        state_tracker_client.set_id("revocation_consumer".to_string());
+
+        let instance_id = match config.instance_id() {
+            Some(instance_id) => instance_id.to_string(),
+            None => Uuid::new_v4().to_string(),
+        };
+        let queue_name = match config.queue_consumer().queue().name() {
+            "" => format!("{}.{}", config.exchange(), instance_id),
+            queue_name => format!("{}.{}", queue_name, instance_id),
+        };
+
+        RevocationConsumer {
+            channel,
+            connection_supervisor,
+            consumer: None,
+            queue_name,
+            config,
+            revocation_list,
+            state_tracker_client,
+        }
+    }
+
+    /// Blocks thread until the shutdown has been requested.
+    pub async fn run(mut self, mut shutdown_signal: ShutdownSignal) -> Result<(), Error> {
+        let acknowledge_options = *self.config.queue_consumer().acknowledge();
+        let reject_options = *self.config.queue_consumer().reject();
+
+        let mut consumer = match self.consumer.take() {
+            Some(consumer) => consumer,
+            None => self.try_set_up().await?,
+        };
+
+        loop {
+            let next_delivery = tokio::select! {
+                next_delivery = consumer.try_next() => next_delivery,
+                _ = shutdown_signal.requested() => break,
+            };
+
+            let delivery = match next_delivery {
+                Ok(Some(delivery)) => delivery,
+                Ok(None) => {
+                    log::warn!("revocation consumer has stopped");
+
+                    consumer = tokio::select! {
+                        consumer = self.reconnect() => consumer,
+                        _ = shutdown_signal.requested() => break,
+                    };
+                    continue;
+                }
+                Err(error) => {
+                    self.handle_error(format!("revocation consumer got an error: {}", error))
+                        .await;
+
+                    if !self.channel.status().connected() {
+                        consumer = tokio::select! {
+                            consumer = self.reconnect() => consumer,
+                            _ = shutdown_signal.requested() => break,
+                        };
+                    }
+
+                    continue;
+                }
+            };
+
+            match self.revoke(&delivery) {
+                Ok(()) => {
+                    if let Err(error) = delivery.ack(acknowledge_options).await {
+                        self.handle_error(format!("failed to acknowledge revocation: {}", error))
+                            .await;
+                    }
+                }
+                Err(error) => {
+                    log::warn!("failed to revoke: {}", error);
+
+                    if let Err(error) = delivery.reject(reject_options).await {
+                        self.handle_error(format!("failed to reject revocation: {}", error))
+                            .await;
+                    }
+                }
+            }
+        }
+
+        if self.channel.status().connected() {
+            if let Err(error) = self
+                .channel
+                .basic_cancel(consumer.tag().as_str(), BasicCancelOptions::default())
+                .await
+            {
+                log::warn!("failed to cancel revocation consumer: {}", error);
+            }
+
+            shutdown::close_channel(&self.channel).await?;
+        }
+
+        match self.state_tracker_client.send_state(State::Idle).await {
+            Ok(_) => (),
+            Err(error) => log::warn!("failed to send idle state: {}", error),
+        }
+
+        Ok(())
+    }
+
+    /// Declares and consumes the queue ahead of [RevocationConsumer::run].
+    pub async fn try_declare(&mut self) -> Result<(), Error> {
+        self.consumer = Some(self.try_set_up().await?);
+
+        Ok(())
+    }
+
+    /// Declares the fanout exchange along with the instance's durable queue bound to it,
+    /// then sets the qos of the channel before consuming the queue. The queue keeps the
+    /// revocations published while reconnecting, and expires once left unconsumed.
+    async fn try_set_up(&self) -> Result<Consumer, Error> {
+        let queue_consumer = self.config.queue_consumer();
+
+        if let Err(error) = self
+            .channel
+            .exchange_declare(
+                self.config.exchange(),
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+        {
+            return Err(Error::new(
+                ErrorKind::AmqpFailure,
+                format!("failed to declare revocation exchange: {}", error),
+            ));
+        }
+
+        let mut arguments = queue_consumer.queue().declare().arguments().clone();
+        arguments.insert(
+            ShortString::from(QUEUE_EXPIRY_ARGUMENT),
+            AMQPValue::LongLongInt(
+                self.config.queue_expiry_in_seconds().saturating_mul(1000).min(i64::MAX as u64)
+                    as i64,
+            ),
+        );
+
+        if let Err(error) = self
+            .channel
+            .queue_declare(
+                self.queue_name.as_str(),
+                QueueDeclareOptions {
+                    durable: true,
+                    ..QueueDeclareOptions::default()
+                },
+                arguments,
+            )
+            .await
+        {
+            return Err(Error::new(
+                ErrorKind::AmqpFailure,
+                format!("failed to declare queue: {}", error),
+            ));
+        }
+
+        if let Err(error) = self
+            .channel
+            .queue_bind(
+                self.queue_name.as_str(),
+                self.config.exchange(),
+                "",
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+        {
+            return Err(Error::new(
+                ErrorKind::AmqpFailure,
+                format!("failed to bind queue to revocation exchange: {}", error),
+            ));
+        }
+
+        match self
+            .channel
+            .basic_qos(
+                queue_consumer.qos().prefetch_count(),
+                *queue_consumer.qos().options(),
+            )
+            .await
+        {
+            Ok(()) => (),
+            Err(error) => {
+                return Err(Error::new(
+                    ErrorKind::AmqpFailure,
+                    format!("failure basic qos: {}", error),
+                ));
+            }
+        }
+
+        self.try_get_consumer(self.queue_name.as_str()).await
+    }
+
+    /// Retries until the queue is consumed again, using a new channel whenever
+    /// the current one has been closed.
+    async fn reconnect(&mut self) -> Consumer {
+        let mut backoff = self.connection_supervisor.backoff();
+
+        loop {
+            backoff.wait().await;
+
+            match self.try_reconnect().await {
+                Ok(consumer) => {
+                    log::info!("revocation consumer has reconnected");
+
+                    match self.state_tracker_client.send_state(State::Valid).await {
+                        Ok(_) => (),
+                        Err(error) => log::warn!("failed to send valid state: {}", error),
+                    }
+
+                    return consumer;
+                }
+                Err(error) => {
+                    self.handle_error(format!("revocation consumer failed to reconnect: {}", error))
+                        .await;
+                }
+            }
+        }
+    }
+
+    async fn try_reconnect(&mut self) -> Result<Consumer, Error> {
+        if !self.channel.status().connected() {
+            self.channel = self.connection_supervisor.try_get_channel().await?;
+        }
+
+        self.try_set_up().await
+    }
+
+    fn revoke(&self, delivery: &Delivery) -> Result<(), Error> {
+        let revocation = match serde_json::from_slice::<Revocation>(delivery.data.as_slice()) {
+            Ok(revocation) => revocation,
+            Err(error) => {
+                return Err(Error::new(
+                    ErrorKind::MalformedRequest,
+                    format!("delivery is not a revocation: {}", error),
+                ));
+            }
+        };
+
+        self.revocation_list.revoke(revocation)
+    }
+
+    async fn try_get_consumer(&self, queue_name: &str) -> Result<Consumer, Error> {
+        let consumer_tag = format!("{}#{}", queue_name, Uuid::new_v4());
+        let consume = self.config.queue_consumer().consume();
+
+        match self
+            .channel
+            .basic_consume(
+                queue_name,
+                consumer_tag.as_str(),
+                *consume.options(),
+                consume.arguments().clone(),
+            )
+            .await
+        {
+            Ok(consumer) => Ok(consumer),
+            Err(error) => Err(Error::new(
+                ErrorKind::AmqpFailure,
+                format!("failure basic consume: {}", error),
+            )),
+        }
+    }
+
+    async fn handle_error(&self, error_message: String) {
+        log::warn!("{}", error_message);
+
+        match self
+            .state_tracker_client
+            .send_state(State::Error(error_message))
+            .await
+        {
+            Ok(_) => (),
+            Err(error) => log::error!("failed to send error state: {}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{Header, TokenData};
+    use lapin::BasicProperties;
+    use serde_json::json;
+
+    use crate::api::input::token::Token;
+    use crate::api::shutdown::ApiHandle;
+    use crate::test_support;
+    use crate::test_support::fake_broker::{self, FakeBroker};
+
+    use super::*;
+
+    fn token(jti: &str) -> Token {
+        let claims = serde_json::from_value(json!({ "jti": jti, "permissions": ["read:items"] }));
+
+        Token::try_new(TokenData {
+            header: Header::default(),
+            claims: claims.unwrap(),
+        })
+        .unwrap()
+    }
+
+    const QUEUE: &str = "token_revocations.instance";
+
+    #[tokio::test]
+    async fn consumes_same_durable_queue_once_disconnected() {
+        let broker = FakeBroker::start().await;
+        let connection_supervisor = broker.connection_supervisor();
+        let channel = connection_supervisor.try_get_channel().await.unwrap();
+        let revocation_list = Arc::new(RevocationList::new(60));
+        let mut config = RevocationConfig::new(test_support::queue_consumer("", 0), 60);
+        config.set_instance_id("instance".to_string());
+        config.set_queue_expiry_in_seconds(600);
+        let revocation_consumer = RevocationConsumer::new(
+            channel,
+            connection_supervisor,
+            config,
+            revocation_list.clone(),
+            test_support::state_tracker_client().await,
+        );
+        let api_handle = ApiHandle::new(test_support::state_tracker_client().await);
+
+        let running_consumer = tokio::spawn(revocation_consumer.run(api_handle.input_signal()));
+
+        fake_broker::wait_until("the queue is consumed", || broker.consumers_of(QUEUE) == 1)
+            .await;
+
+        let declaration = broker.declaration_of(QUEUE).unwrap();
+        assert!(declaration.durable);
+        assert!(!declaration.exclusive);
+        assert!(!declaration.auto_delete);
+        assert_eq!(
+            declaration.arguments.inner().get(QUEUE_EXPIRY_ARGUMENT),
+            Some(&AMQPValue::LongLongInt(600_000))
+        );
+
+        broker.disconnect();
+
+        fake_broker::wait_until("the queue is consumed again", || {
+            broker.consumers_of(QUEUE) == 2
+        })
+        .await;
+
+        let revocation = json!({ "claim": "jti", "value": "revoked" });
+        assert!(broker.deliver(
+            QUEUE,
+            BasicProperties::default(),
+            revocation.to_string().as_bytes(),
+        ));
+
+        fake_broker::wait_until("the token is revoked", || {
+            revocation_list.check(&token("revoked")).is_err()
+        })
+        .await;
+        assert!(revocation_list.check(&token("other")).is_ok());
+
+        running_consumer.abort();
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use jsonwebtoken::get_current_timestamp;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api::input::token::Token;
+use crate::error::{Error, ErrorKind};
+
+const ISSUED_AT_CLAIM: &str = "iat";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum RevokedClaim {
+    /// Revokes a single token.
+    Jti,
+    /// Revokes every token of a user issued until the revocation.
+    Sub,
+    /// Revokes every token of a session issued until the revocation.
+    Sid,
+}
+
+impl RevokedClaim {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RevokedClaim::Jti => "jti",
+            RevokedClaim::Sub => "sub",
+            RevokedClaim::Sid => "sid",
+        }
+    }
+}
+
+/// Message consumed from the revocation control queue.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Revocation {
+    pub claim: RevokedClaim,
+    pub value: String,
+    /// Timestamp from which the revoked tokens have expired anyway.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+struct RevocationEntry {
+    revoked_at: u64,
+    expires_at: u64,
+}
+
+/// Deny-list of tokens, keyed by one of their claims.
+/// Entries are dropped once the revoked tokens would have expired anyway.
+pub struct RevocationList {
+    entries: RwLock<HashMap<(RevokedClaim, String), RevocationEntry>>,
+    default_ttl_in_seconds: u64,
+}
+
+impl RevocationList {
+    pub fn new(default_ttl_in_seconds: u64) -> RevocationList {
+        RevocationList {
+            entries: RwLock::new(HashMap::new()),
+            default_ttl_in_seconds,
+        }
+    }
+
+    pub fn revoke(&self, revocation: Revocation) -> Result<(), Error> {
+        let now = get_current_timestamp();
+        let expires_at = revocation
+            .expires_at
+            .unwrap_or(now + self.default_ttl_in_seconds);
+
+        let mut entries = match self.entries.write() {
+            Ok(entries) => entries,
+            Err(error) => {
+                return Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("failed to write revocation list: {}", error),
+                ));
+            }
+        };
+
+        entries.retain(|_, entry| entry.expires_at > now);
+
+        if expires_at > now {
+            entries.insert(
+                (revocation.claim, revocation.value),
+                RevocationEntry {
+                    revoked_at: now,
+                    expires_at,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn check(&self, token: &Token) -> Result<(), Error> {
+        let entries = match self.entries.read() {
+            Ok(entries) => entries,
+            Err(error) => {
+                return Err(Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("failed to read revocation list: {}", error),
+                ));
+            }
+        };
+
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let now = get_current_timestamp();
+
+        for claim in [RevokedClaim::Jti, RevokedClaim::Sub, RevokedClaim::Sid] {
+            let value = match token.get(claim.name()).and_then(Value::as_str) {
+                Some(value) => value,
+                None => continue,
+            };
+
+            let entry = match entries.get(&(claim, value.to_string())) {
+                Some(entry) if entry.expires_at > now => entry,
+                _ => continue,
+            };
+
+            // Tokens issued after revoking a user or session are valid again.
+            let issued_after_revocation = match token.get(ISSUED_AT_CLAIM).and_then(Value::as_u64) {
+                Some(issued_at) => issued_at > entry.revoked_at,
+                None => false,
+            };
+
+            if claim == RevokedClaim::Jti || !issued_after_revocation {
+                return Err(Error::new(
+                    ErrorKind::RevokedToken,
+                    format!("token has been revoked by its '{}' claim", claim.name()),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{Header, TokenData};
+    use serde_json::json;
+
+    use super::*;
+
+    fn token(mut claims: Value) -> Token {
+        claims["permissions"] = json!(["read:items"]);
+
+        Token::try_new(TokenData {
+            header: Header::default(),
+            claims: serde_json::from_value(claims).unwrap(),
+        })
+        .unwrap()
+    }
+
+    fn revocation(claim: RevokedClaim, value: &str) -> Revocation {
+        Revocation {
+            claim,
+            value: value.to_string(),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn forgets_revocations_once_expired() {
+        let revocation_list = RevocationList::new(1);
+        revocation_list
+            .revoke(revocation(RevokedClaim::Jti, "revoked"))
+            .unwrap();
+        let revoked_token = token(json!({ "jti": "revoked" }));
+
+        assert!(revocation_list.check(&revoked_token).is_err());
+
+        std::thread::sleep(std::time::Duration::from_millis(2100));
+
+        assert!(revocation_list.check(&revoked_token).is_ok());
+    }
+
+    #[test]
+    fn ignores_revocations_already_expired() {
+        let revocation_list = RevocationList::new(60);
+        revocation_list
+            .revoke(Revocation {
+                expires_at: Some(get_current_timestamp() - 1),
+                ..revocation(RevokedClaim::Jti, "revoked")
+            })
+            .unwrap();
+
+        assert!(revocation_list
+            .check(&token(json!({ "jti": "revoked" })))
+            .is_ok());
+    }
+
+    #[test]
+    fn revokes_tokens_of_user_or_session_issued_until_revocation() {
+        let revocation_list = RevocationList::new(60);
+        revocation_list
+            .revoke(revocation(RevokedClaim::Sub, "user"))
+            .unwrap();
+        revocation_list
+            .revoke(revocation(RevokedClaim::Sid, "session"))
+            .unwrap();
+        let now = get_current_timestamp();
+
+        for claim in ["sub", "sid"] {
+            let value = match claim {
+                "sub" => "user",
+                _ => "session",
+            };
+
+            let error = revocation_list
+                .check(&token(json!({ claim: value, "iat": now - 10 })))
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), ErrorKind::RevokedToken);
+            assert!(revocation_list
+                .check(&token(json!({ claim: value })))
+                .is_err());
+            assert!(revocation_list
+                .check(&token(json!({ claim: value, "iat": now + 10 })))
+                .is_ok());
+        }
+
+        assert!(revocation_list
+            .check(&token(json!({ "sub": "other", "iat": now - 10 })))
+            .is_ok());
+    }
+
+    #[test]
+    fn keeps_revoked_jti_revoked() {
+        let revocation_list = RevocationList::new(60);
+        revocation_list
+            .revoke(revocation(RevokedClaim::Jti, "revoked"))
+            .unwrap();
+        let now = get_current_timestamp();
+
+        assert!(revocation_list
+            .check(&token(json!({ "jti": "revoked", "iat": now + 10 })))
+            .is_err());
+        assert!(revocation_list
+            .check(&token(json!({ "jti": "other", "iat": now - 10 })))
+            .is_ok());
+    }
+}
@@ -3,12 +3,19 @@ use crate::api::input::request::Request;
 
 use crate::error::{Error, ErrorKind};
 
+#[deprecated(note = "requests are sanitized by the pipeline, see 'validate_action'")]
 pub fn sanitize(
     raw_request: Map<String, Value>,
     actions: &'static [&'static str],
 ) -> Result<Request, Error> {
     let request = Request::new(raw_request);
 
+    validate_action(&request, actions)?;
+
+    Ok(request)
+}
+
+pub fn validate_action(request: &Request, actions: &'static [&'static str]) -> Result<(), Error> {
     let header = request.try_get_header()?;
 
     if !actions
@@ -21,5 +28,5 @@ pub fn sanitize(
         ));
     }
 
-    Ok(request)
+    Ok(())
 }
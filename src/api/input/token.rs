@@ -1,8 +1,13 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use jsonwebtoken::TokenData;
-use serde_json::Value;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
 
+use crate::api::input::permission_set::PermissionSet;
+use crate::config::permission_mappings::PermissionMappings;
 use crate::error::{Error, ErrorKind};
 
 const PERMISSIONS_CLAIM: &str = "permissions";
@@ -13,34 +18,126 @@ const CUSTOM_PERMISSIONS_CLAIM: &str = "permission";
 
 pub const USER_ID_CLAIM: &str = "sub";
 pub const ORGANIZATION_ID_CLAIM: &str = "organization_id";
+pub const EXPIRATION_CLAIM: &str = "exp";
+pub const ISSUER_CLAIM: &str = "iss";
+pub const AUDIENCE_CLAIM: &str = "aud";
+pub const EMAIL_CLAIM: &str = "email";
+
+/// The audience may be either a single string or an array of strings.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
 
 #[derive(Debug)]
 pub struct Token {
     token_data: TokenData<HashMap<String, Value>>,
-    permissions: Vec<String>,
+    permissions: PermissionSet,
+    /// Prebuilt sets of the token's roles and scopes, shared between tokens.
+    mapped_permissions: Vec<Arc<PermissionSet>>,
+}
+
+// TokenData does not implement Clone.
+impl Clone for Token {
+    fn clone(&self) -> Self {
+        Token {
+            token_data: TokenData {
+                header: self.token_data.header.clone(),
+                claims: self.token_data.claims.clone(),
+            },
+            permissions: self.permissions.clone(),
+            mapped_permissions: self.mapped_permissions.clone(),
+        }
+    }
 }
 
 impl Token {
     pub fn try_new(token_data: TokenData<HashMap<String, Value>>) -> Result<Token, Error> {
-        let mut permissions = get_permissions_from_claim(&token_data, PERMISSIONS_CLAIM)?;
+        Token::try_new_with_mappings(token_data, &PermissionMappings::default())
+    }
+
+    /// Effective permissions are the ones granted directly plus the ones mapped from
+    /// the token's roles and scopes. Whenever any mapping is configured, tokens without
+    /// permissions claims are not malformed.
+    pub fn try_new_with_mappings(
+        token_data: TokenData<HashMap<String, Value>>,
+        permission_mappings: &PermissionMappings,
+    ) -> Result<Token, Error> {
+        let permissions_required = !permission_mappings.has_fallback();
+
+        let mut permissions =
+            get_permissions_from_claim(&token_data, PERMISSIONS_CLAIM, permissions_required)?;
 
         // Use custom permissions claim *ONLY* if Auth0's permission claim is empty.
         if permissions.is_empty() {
-            permissions = get_permissions_from_claim(&token_data, CUSTOM_PERMISSIONS_CLAIM)?;
+            permissions = get_permissions_from_claim(
+                &token_data,
+                CUSTOM_PERMISSIONS_CLAIM,
+                permissions_required,
+            )?;
         }
 
+        let mapped_permissions = permission_mappings.permission_sets(&token_data.claims);
+
         Ok(Token {
             token_data,
-            permissions,
+            permissions: PermissionSet::from_permissions(&permissions),
+            mapped_permissions,
         })
     }
 
+    /// See [PermissionSet] for how wildcard and hierarchical permissions are matched.
     pub fn has_permission(&self, permission: &String) -> Result<(), Error> {
-        if !self.permissions.contains(permission) {
-            return Err(Error::new(
-                ErrorKind::PermissionNotFound,
-                format!("permission '{}' could not be found", permission),
-            ));
+        self.has_permission_in_tenant(permission, None)
+    }
+
+    /// Tokens are members of the tenant they belong to and of the tenants
+    /// they have tenant-scoped grants for.
+    pub fn is_member_of(&self, tenant: &str) -> bool {
+        self.organization_id().ok() == Some(tenant)
+            || std::iter::once(&self.permissions)
+                .chain(self.mapped_permissions.iter().map(Arc::as_ref))
+                .any(|permission_set| permission_set.has_tenant(tenant))
+    }
+
+    /// Within a tenant, unscoped grants apply only whenever the token belongs to
+    /// that tenant, while grants scoped to it apply regardless.
+    pub fn has_permission_in_tenant(
+        &self,
+        permission: &String,
+        tenant: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut permission_sets =
+            std::iter::once(&self.permissions).chain(self.mapped_permissions.iter().map(Arc::as_ref));
+
+        let granted = match tenant {
+            Some(tenant) => {
+                let belongs_to_tenant = self.organization_id().ok() == Some(tenant);
+
+                permission_sets.any(|permission_set| {
+                    (belongs_to_tenant && permission_set.contains(permission))
+                        || permission_set.contains_in_tenant(permission, tenant)
+                })
+            }
+            None => permission_sets.any(|permission_set| permission_set.contains(permission)),
+        };
+
+        if !granted {
+            return Err(match tenant {
+                Some(tenant) => Error::new(
+                    ErrorKind::PermissionNotFound,
+                    format!(
+                        "permission '{}' could not be found for tenant '{}'",
+                        permission, tenant
+                    ),
+                ),
+                None => Error::new(
+                    ErrorKind::PermissionNotFound,
+                    format!("permission '{}' could not be found", permission),
+                ),
+            });
         }
 
         Ok(())
@@ -49,11 +146,87 @@ impl Token {
     pub fn get(&self, key: &str) -> Option<&Value> {
         self.token_data.claims.get(key)
     }
+
+    /// Deserializes the whole claim set into a user defined type.
+    pub fn claims<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let claims: Map<String, Value> = self
+            .token_data
+            .claims
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        match serde_json::from_value::<T>(Value::Object(claims)) {
+            Ok(claims) => Ok(claims),
+            Err(error) => Err(Error::new(
+                ErrorKind::MalformedToken,
+                format!("failed to deserialize claims: {}", error),
+            )),
+        }
+    }
+
+    pub fn try_get_claim<T: DeserializeOwned>(&self, claim: &str) -> Result<T, Error> {
+        match self.token_data.claims.get(claim) {
+            Some(raw_value) => match serde_json::from_value::<T>(raw_value.clone()) {
+                Ok(value) => Ok(value),
+                Err(error) => Err(Error::new(
+                    ErrorKind::MalformedToken,
+                    format!("failed to read '{}' claim: {}", claim, error),
+                )),
+            },
+            None => Err(Error::new(
+                ErrorKind::MalformedToken,
+                format!("token has no '{}' claim", claim),
+            )),
+        }
+    }
+
+    pub fn sub(&self) -> Result<&str, Error> {
+        self.try_get_str_claim(USER_ID_CLAIM)
+    }
+
+    pub fn organization_id(&self) -> Result<&str, Error> {
+        self.try_get_str_claim(ORGANIZATION_ID_CLAIM)
+    }
+
+    pub fn exp(&self) -> Result<u64, Error> {
+        self.try_get_claim(EXPIRATION_CLAIM)
+    }
+
+    pub fn iss(&self) -> Result<&str, Error> {
+        self.try_get_str_claim(ISSUER_CLAIM)
+    }
+
+    pub fn aud(&self) -> Result<Vec<String>, Error> {
+        match self.try_get_claim::<Audience>(AUDIENCE_CLAIM)? {
+            Audience::Single(audience) => Ok(vec![audience]),
+            Audience::Multiple(audience) => Ok(audience),
+        }
+    }
+
+    pub fn email(&self) -> Result<&str, Error> {
+        self.try_get_str_claim(EMAIL_CLAIM)
+    }
+
+    fn try_get_str_claim(&self, claim: &str) -> Result<&str, Error> {
+        match self.token_data.claims.get(claim) {
+            Some(Value::String(value)) => Ok(value.as_str()),
+            Some(_) => Err(Error::new(
+                ErrorKind::MalformedToken,
+                format!("failed to read '{}' claim: expected a string", claim),
+            )),
+            None => Err(Error::new(
+                ErrorKind::MalformedToken,
+                format!("token has no '{}' claim", claim),
+            )),
+        }
+    }
 }
 
 fn get_permissions_from_claim(
     token_data: &TokenData<HashMap<String, Value>>,
     claim: &str,
+    required: bool,
 ) -> Result<Vec<String>, Error> {
     let permissions = match token_data.claims.get(claim) {
         Some(permissions) => match serde_json::from_value::<Vec<String>>(permissions.clone()) {
@@ -68,6 +241,7 @@ fn get_permissions_from_claim(
                 ))
             }
         },
+        None if !required => Vec::new(),
         None => {
             return Err(Error::new(
                 ErrorKind::MalformedToken,
@@ -78,3 +252,117 @@ fn get_permissions_from_claim(
 
     Ok(permissions)
 }
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::Header;
+    use serde_json::json;
+
+    use super::*;
+
+    fn token(claims: Value) -> Token {
+        let mut claims: HashMap<String, Value> = serde_json::from_value(claims).unwrap();
+        claims.insert(PERMISSIONS_CLAIM.to_string(), json!(["read:items"]));
+
+        Token::try_new(TokenData {
+            header: Header::default(),
+            claims,
+        })
+        .unwrap()
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Claims {
+        sub: String,
+        exp: u64,
+    }
+
+    #[test]
+    fn reads_registered_claims() {
+        let token = token(json!({
+            "sub": "user",
+            "organization_id": "organization",
+            "exp": 1_700_000_000u64,
+            "iss": "https://issuer",
+            "email": "user@example.com",
+        }));
+
+        assert_eq!(token.sub().unwrap(), "user");
+        assert_eq!(token.organization_id().unwrap(), "organization");
+        assert_eq!(token.exp().unwrap(), 1_700_000_000);
+        assert_eq!(token.iss().unwrap(), "https://issuer");
+        assert_eq!(token.email().unwrap(), "user@example.com");
+    }
+
+    #[test]
+    fn reads_single_audience() {
+        let token = token(json!({ "aud": "api" }));
+
+        assert_eq!(token.aud().unwrap(), vec!["api".to_string()]);
+    }
+
+    #[test]
+    fn reads_multiple_audiences() {
+        let token = token(json!({ "aud": ["api", "admin"] }));
+
+        assert_eq!(
+            token.aud().unwrap(),
+            vec!["api".to_string(), "admin".to_string()]
+        );
+    }
+
+    #[test]
+    fn names_missing_claim() {
+        let token = token(json!({}));
+
+        for error in [
+            token.sub().unwrap_err(),
+            token.exp().unwrap_err(),
+            token.aud().unwrap_err(),
+        ] {
+            assert_eq!(error.kind(), ErrorKind::MalformedToken);
+        }
+
+        assert_eq!(token.sub().unwrap_err().message, "token has no 'sub' claim");
+        assert_eq!(token.exp().unwrap_err().message, "token has no 'exp' claim");
+        assert_eq!(token.aud().unwrap_err().message, "token has no 'aud' claim");
+    }
+
+    #[test]
+    fn fails_on_claim_of_wrong_type() {
+        let token = token(json!({ "sub": 42, "exp": "tomorrow", "aud": 42 }));
+
+        let error = token.sub().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MalformedToken);
+        assert!(error.message.contains("'sub'"));
+
+        let error = token.exp().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::MalformedToken);
+        assert!(error.message.contains("'exp'"));
+
+        assert!(token.aud().unwrap_err().message.contains("'aud'"));
+    }
+
+    #[test]
+    fn deserializes_claims_into_user_defined_type() {
+        let token = token(json!({ "sub": "user", "exp": 1_700_000_000u64 }));
+
+        assert_eq!(
+            token.claims::<Claims>().unwrap(),
+            Claims {
+                sub: "user".to_string(),
+                exp: 1_700_000_000
+            }
+        );
+    }
+
+    #[test]
+    fn fails_to_deserialize_claims_missing_a_field() {
+        let token = token(json!({ "sub": "user" }));
+
+        let error = token.claims::<Claims>().unwrap_err();
+
+        assert_eq!(error.kind(), ErrorKind::MalformedToken);
+        assert!(error.message.contains("exp"));
+    }
+}
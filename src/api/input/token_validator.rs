@@ -1,24 +1,77 @@
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
 
+use crate::api::input::key_set::{KeySet, VerificationKey};
 use crate::api::input::token::Token;
+use crate::api::shutdown::ShutdownSignal;
+use crate::config::jwks_source::JwksSource;
 use crate::config::openid_connect_config::OpenIdConnectConfig;
-use jsonwebtoken::{decode, decode_header, jwk::AlgorithmParameters, DecodingKey, Validation};
+use crate::config::permission_mappings::PermissionMappings;
+use cooplan_state_tracker::state_tracker_client::StateTrackerClient;
+use jsonwebtoken::{decode, decode_header, get_current_timestamp};
 use serde_json::Value;
 
 use crate::config::token_validator_config;
 use crate::config::token_validator_config::TokenValidatorConfig;
 use crate::error::{Error, ErrorKind};
 
+const ISSUED_AT_CLAIM: &str = "iat";
+
 pub struct TokenValidator {
-    config: TokenValidatorConfig,
+    key_set: Arc<KeySet>,
+    permission_mappings: PermissionMappings,
 }
 
 impl TokenValidator {
-    pub fn new(config: TokenValidatorConfig) -> TokenValidator {
-        TokenValidator { config }
+    pub fn new(
+        config: TokenValidatorConfig,
+        state_tracker_client: StateTrackerClient,
+    ) -> TokenValidator {
+        let (jwks, openid_connect, permission_mappings) = config.owned_parts();
+
+        TokenValidator {
+            key_set: Arc::new(KeySet::new(jwks, openid_connect, state_tracker_client)),
+            permission_mappings,
+        }
+    }
+
+    pub fn key_set(&self) -> Arc<KeySet> {
+        self.key_set.clone()
     }
 
-    pub fn validate(&self, token: &str) -> Result<Token, Error> {
+    /// Keeps the jwks up to date until the shutdown has been requested, either by
+    /// refreshing it periodically or by watching the file it is read from.
+    pub async fn run(&self, mut shutdown_signal: ShutdownSignal) -> Result<(), Error> {
+        let openid_connect = self.key_set.openid_connect();
+        let refresh_interval = openid_connect.jwks_refresh_interval_in_seconds();
+
+        match openid_connect.jwks_source().clone() {
+            JwksSource::Http if refresh_interval > 0 => {
+                self.key_set()
+                    .run_periodic_refresh(Duration::from_secs(refresh_interval), shutdown_signal)
+                    .await
+            }
+            JwksSource::File {
+                path,
+                poll_interval_in_seconds,
+            } => {
+                self.key_set()
+                    .run_file_watch(
+                        path,
+                        Duration::from_secs(poll_interval_in_seconds),
+                        shutdown_signal,
+                    )
+                    .await
+            }
+            _ => {
+                shutdown_signal.requested().await;
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn validate(&self, token: &str) -> Result<Token, Error> {
         let header = match decode_header(token) {
             Ok(header) => header,
             Err(error) => {
@@ -39,72 +92,409 @@ impl TokenValidator {
             }
         };
 
-        let jwk = match self.config.jwks().find(&kid) {
-            Some(jwk) => jwk,
-            None => {
-                return Err(Error::new(
-                    ErrorKind::MalformedToken,
-                    format!("failed to find jwk for kid '{}'", kid),
-                ));
-            }
-        };
+        let verification_key = self.try_find_verification_key(&kid).await?;
 
-        let rsa = match jwk.algorithm {
-            AlgorithmParameters::RSA(ref rsa) => rsa,
-            _ => {
+        let decoded_token = match decode::<HashMap<String, Value>>(
+            token,
+            verification_key.decoding_key(),
+            verification_key.validation(),
+        ) {
+            Ok(decoded_token) => decoded_token,
+            Err(error) => {
                 return Err(Error::new(
-                    ErrorKind::MalformedToken,
-                    format!("expected 'RSA' algorithm got '{:?}'", jwk.algorithm),
+                    ErrorKind::InvalidToken,
+                    format!("invalid token detected: {}", error),
                 ));
             }
         };
 
-        let decoding_key = match DecodingKey::from_rsa_components(&rsa.n, &rsa.e) {
-            Ok(decoding_key) => decoding_key,
-            Err(error) => {
-                return Err(Error::new(
-                    ErrorKind::TokenDecodingFailure,
-                    format!("failed to get decoding key: {}", error),
-                ));
+        validate_claims(&decoded_token.claims, self.key_set.openid_connect())?;
+
+        let wrapped_token = Token::try_new_with_mappings(decoded_token, &self.permission_mappings)?;
+        Ok(wrapped_token)
+    }
+
+    /// Looks for the kid within the key set, refreshing it in case the kid is unknown,
+    /// since the identity provider may have rotated its signing keys.
+    async fn try_find_verification_key(&self, kid: &str) -> Result<Arc<VerificationKey>, Error> {
+        if let Some(verification_key) = self.key_set.find(kid)? {
+            return Ok(verification_key);
+        }
+
+        if self.key_set.try_refresh_on_demand().await {
+            if let Some(verification_key) = self.key_set.find(kid)? {
+                return Ok(verification_key);
             }
-        };
+        }
+
+        Err(Error::new(
+            ErrorKind::MalformedToken,
+            format!("failed to find jwk for kid '{}'", kid),
+        ))
+    }
+}
 
-        let algorithm = match jwk.common.algorithm {
-            Some(algorithm) => algorithm,
+/// Validates the claims which are not covered by jsonwebtoken's validation, since it
+/// only enforces the presence of the registered claims it knows about.
+fn validate_claims(
+    claims: &HashMap<String, Value>,
+    openid_connect: &OpenIdConnectConfig,
+) -> Result<(), Error> {
+    if let Some(missing_claim) = openid_connect
+        .required_claims()
+        .iter()
+        .find(|claim| !claims.contains_key(claim.as_str()))
+    {
+        return Err(Error::new(
+            ErrorKind::InvalidToken,
+            format!("token is missing required claim '{}'", missing_claim),
+        ));
+    }
+
+    if let Some(max_token_age) = openid_connect.max_token_age_in_seconds() {
+        let issued_at = match claims.get(ISSUED_AT_CLAIM).and_then(Value::as_u64) {
+            Some(issued_at) => issued_at,
             None => {
                 return Err(Error::new(
-                    ErrorKind::TokenDecodingFailure,
-                    "jwk is missing algorithm",
+                    ErrorKind::InvalidToken,
+                    format!("token has no valid '{}' claim", ISSUED_AT_CLAIM),
                 ));
             }
         };
 
-        let mut validation = Validation::new(algorithm);
-        validation.validate_exp = true;
-        validation.set_audience(self.config.open_id_connect().audience());
-        validation.set_issuer(self.config.open_id_connect().issuers());
-
-        let decoded_token =
-            match decode::<HashMap<String, Value>>(token, &decoding_key, &validation) {
-                Ok(decoded_token) => decoded_token,
-                Err(error) => {
-                    return Err(Error::new(
-                        ErrorKind::InvalidToken,
-                        format!("invalid token detected: {}", error),
-                    ));
-                }
-            };
+        let max_issued_at_age = max_token_age + openid_connect.leeway_in_seconds();
 
-        let wrapped_token = Token::try_new(decoded_token)?;
-        Ok(wrapped_token)
+        if get_current_timestamp().saturating_sub(issued_at) > max_issued_at_age {
+            return Err(Error::new(
+                ErrorKind::InvalidToken,
+                format!("token is older than {} seconds", max_token_age),
+            ));
+        }
     }
+
+    Ok(())
 }
 
 pub async fn try_generate_token_validator(
     openid_connect: OpenIdConnectConfig,
+    permission_mappings: PermissionMappings,
+    mut state_tracker_client: StateTrackerClient,
 ) -> Result<TokenValidator, Error> {
-    let token_validator_config =
+    let mut token_validator_config =
         token_validator_config::try_generate_config(openid_connect).await?;
+    token_validator_config.set_permission_mappings(permission_mappings);
+
+    state_tracker_client.set_id("token_validator".to_string());
+
+    Ok(TokenValidator::new(token_validator_config, state_tracker_client))
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::jwk::JwkSet;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use serde_json::json;
+
+    use crate::config::jwks_source::JwksSource;
+    use crate::config::token_validator_config::TokenValidatorConfig;
+    use crate::test_support;
+    use crate::test_support::TemporaryFile;
+
+    use super::*;
+
+    const KID: &str = "test";
+    const SECRET: &[u8] = b"token validator test secret";
+    /// Base64url encoding of [SECRET].
+    const ENCODED_SECRET: &str = "dG9rZW4gdmFsaWRhdG9yIHRlc3Qgc2VjcmV0";
+    const ISSUER: &str = "https://issuer.example";
+    const AUDIENCE: &str = "api";
+
+    async fn validator(configure: impl FnOnce(&mut OpenIdConnectConfig)) -> TokenValidator {
+        let jwks: JwkSet = serde_json::from_value(json!({
+            "keys": [{"kty": "oct", "kid": KID, "k": ENCODED_SECRET}]
+        }))
+        .unwrap();
+
+        let mut openid_connect = OpenIdConnectConfig::new(
+            String::new(),
+            vec![ISSUER.to_string()],
+            vec![AUDIENCE.to_string()],
+        );
+        openid_connect.set_algorithms(vec![Algorithm::HS256]);
+        openid_connect.set_jwks_source(JwksSource::Inline { jwks: jwks.clone() });
+        configure(&mut openid_connect);
+
+        TokenValidator::new(
+            TokenValidatorConfig::new(jwks, openid_connect),
+            test_support::state_tracker_client().await,
+        )
+    }
+
+    fn now() -> i64 {
+        get_current_timestamp() as i64
+    }
+
+    /// Signs the claims along with valid 'iss', 'aud', 'exp' and 'permissions' claims,
+    /// unless given. Claims given as null are left out.
+    fn sign(claims: Value) -> String {
+        let mut all_claims = json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "exp": now() + 3600,
+            "permissions": ["read:element"],
+        });
+
+        for (claim, value) in claims.as_object().unwrap() {
+            match value {
+                Value::Null => all_claims.as_object_mut().unwrap().remove(claim),
+                _ => all_claims
+                    .as_object_mut()
+                    .unwrap()
+                    .insert(claim.clone(), value.clone()),
+            };
+        }
+
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(KID.to_string());
+
+        encode(&header, &all_claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
 
-    Ok(TokenValidator::new(token_validator_config))
+    async fn validate(
+        configure: impl FnOnce(&mut OpenIdConnectConfig),
+        claims: Value,
+    ) -> Result<Token, Error> {
+        validator(configure).await.validate(sign(claims).as_str()).await
+    }
+
+    fn assert_invalid(result: Result<Token, Error>, message: &str) {
+        match result {
+            Ok(_) => panic!("token should be invalid: {}", message),
+            Err(error) => {
+                assert_eq!(error.kind(), ErrorKind::InvalidToken);
+                assert!(
+                    error.message.contains(message),
+                    "'{}' does not contain '{}'",
+                    error.message,
+                    message
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_token() {
+        assert!(validate(|_| (), json!({})).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_token_signed_with_another_secret() {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(KID.to_string());
+        let token = encode(
+            &header,
+            &json!({"iss": ISSUER, "aud": AUDIENCE, "exp": now() + 3600}),
+            &EncodingKey::from_secret(b"another secret"),
+        )
+        .unwrap();
+
+        let result = validator(|_| ()).await.validate(token.as_str()).await;
+
+        assert_invalid(result, "InvalidSignature");
+    }
+
+    #[tokio::test]
+    async fn tolerates_expiration_within_leeway() {
+        let result = validate(
+            |openid_connect| openid_connect.set_leeway_in_seconds(60),
+            json!({"exp": now() - 30}),
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_expiration_beyond_leeway() {
+        let result = validate(
+            |openid_connect| openid_connect.set_leeway_in_seconds(60),
+            json!({"exp": now() - 120}),
+        )
+        .await;
+
+        assert_invalid(result, "ExpiredSignature");
+    }
+
+    #[tokio::test]
+    async fn ignores_nbf_unless_validated() {
+        let result = validate(|_| (), json!({"nbf": now() + 600})).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_future_nbf_when_validated() {
+        let result = validate(
+            |openid_connect| openid_connect.set_validate_nbf(true),
+            json!({"nbf": now() + 600}),
+        )
+        .await;
+
+        assert_invalid(result, "ImmatureSignature");
+    }
+
+    #[tokio::test]
+    async fn tolerates_nbf_within_leeway() {
+        let result = validate(
+            |openid_connect| {
+                openid_connect.set_validate_nbf(true);
+                openid_connect.set_leeway_in_seconds(60);
+            },
+            json!({"nbf": now() + 30}),
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn accepts_token_younger_than_max_age() {
+        let result = validate(
+            |openid_connect| openid_connect.set_max_token_age_in_seconds(Some(3600)),
+            json!({"iat": now() - 600}),
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn tolerates_max_age_within_leeway() {
+        let result = validate(
+            |openid_connect| {
+                openid_connect.set_max_token_age_in_seconds(Some(3600));
+                openid_connect.set_leeway_in_seconds(60);
+            },
+            json!({"iat": now() - 3630}),
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_token_older_than_max_age() {
+        let result = validate(
+            |openid_connect| {
+                openid_connect.set_max_token_age_in_seconds(Some(3600));
+                openid_connect.set_leeway_in_seconds(60);
+            },
+            json!({"iat": now() - 3700}),
+        )
+        .await;
+
+        assert_invalid(result, "older than 3600 seconds");
+    }
+
+    #[tokio::test]
+    async fn rejects_token_without_iat_when_max_age_is_set() {
+        let result = validate(
+            |openid_connect| openid_connect.set_max_token_age_in_seconds(Some(3600)),
+            json!({}),
+        )
+        .await;
+
+        assert_invalid(result, "no valid 'iat' claim");
+    }
+
+    #[tokio::test]
+    async fn accepts_token_without_iat_unless_max_age_is_set() {
+        let result = validate(|_| (), json!({"iat": null})).await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn accepts_token_with_every_required_claim() {
+        let result = validate(
+            |openid_connect| {
+                openid_connect.set_required_claims(vec![
+                    "sub".to_string(),
+                    "iat".to_string(),
+                    "organization_id".to_string(),
+                ])
+            },
+            json!({"sub": "user", "iat": now(), "organization_id": "organization"}),
+        )
+        .await;
+
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn rejects_token_missing_any_required_claim() {
+        let required_claims = ["sub", "iat", "organization_id"];
+
+        for missing_claim in required_claims {
+            let mut claims = json!({"sub": "user", "iat": now(), "organization_id": "organization"});
+            claims
+                .as_object_mut()
+                .unwrap()
+                .insert(missing_claim.to_string(), Value::Null);
+
+            let result = validate(
+                |openid_connect| {
+                    openid_connect.set_required_claims(
+                        required_claims.iter().map(|claim| claim.to_string()).collect(),
+                    )
+                },
+                claims,
+            )
+            .await;
+
+            assert_invalid(result, missing_claim);
+        }
+    }
+
+    #[test]
+    fn lists_missing_required_claim() {
+        let mut openid_connect = OpenIdConnectConfig::new(String::new(), Vec::new(), Vec::new());
+        openid_connect.set_required_claims(vec!["organization_id".to_string()]);
+
+        let error = validate_claims(&HashMap::new(), &openid_connect).unwrap_err();
+
+        assert_eq!(
+            error.message,
+            "token is missing required claim 'organization_id'"
+        );
+    }
+
+    #[tokio::test]
+    async fn refreshes_keys_for_unknown_kid() {
+        let old_jwks = json!({ "keys": [{"kty": "oct", "kid": "old", "k": ENCODED_SECRET}] });
+        let file = TemporaryFile::new(old_jwks.to_string().as_str());
+        let mut openid_connect = OpenIdConnectConfig::new(
+            String::new(),
+            vec![ISSUER.to_string()],
+            vec![AUDIENCE.to_string()],
+        );
+        openid_connect.set_algorithms(vec![Algorithm::HS256]);
+        openid_connect.set_jwks_source(JwksSource::File {
+            path: file.path().to_string(),
+            poll_interval_in_seconds: 1,
+        });
+        let validator = TokenValidator::new(
+            TokenValidatorConfig::new(serde_json::from_value(old_jwks).unwrap(), openid_connect),
+            test_support::state_tracker_client().await,
+        );
+
+        file.write(
+            json!({ "keys": [{"kty": "oct", "kid": KID, "k": ENCODED_SECRET}] })
+                .to_string()
+                .as_str(),
+        );
+
+        assert!(validator.validate(sign(json!({})).as_str()).await.is_ok());
+        assert!(validator.key_set().find("old").unwrap().is_none());
+    }
 }
@@ -1,4 +1,7 @@
+pub mod connection_supervisor;
 pub mod init;
 pub mod initialization_package;
 pub mod input;
 pub mod output;
+pub mod shutdown;
+pub mod tenant;
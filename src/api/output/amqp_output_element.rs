@@ -3,8 +3,15 @@ use cooplan_lapin_wrapper::config::amqp_output_api::AmqpOutputApi;
 use cooplan_state_tracker::state::State;
 use cooplan_state_tracker::state_tracker_client::StateTrackerClient;
 
+use lapin::protocol::BasicProperties;
+use lapin::types::{AMQPValue, ShortString};
 use lapin::Channel;
 use serde_json::Value;
+
+use crate::api::connection_supervisor::ConnectionSupervisor;
+use crate::api::shutdown;
+use crate::api::tenant;
+use crate::error::{Error, ErrorKind};
 use tokio::sync::mpsc::Receiver;
 
 pub struct AmqpOutputElement {
@@ -35,37 +42,44 @@ impl AmqpOutputElement {
     pub fn owned_output_config(self) -> AmqpOutputApi {
         self.output_config
     }
-}
-
-impl AmqpOutputElement {
-    pub async fn run(self, channel: Arc<Channel>, mut receiver: Receiver<Value>) {
-        let queue = self.output_config.queue();
 
-        match channel
-            .queue_declare(
-                queue.name(),
-                *queue.declare().options(),
-                queue.declare().arguments().clone(),
-            )
-            .await
-        {
-            Ok(_) => (),
-            Err(error) => {
-                handle_error(format!("failed to declare queue for output element '{}': '{}'",
-                                     self.name,
-                                     error), &self.state_tracker).await;
+    /// Data belonging to a tenant is published with the tenant as a header,
+    /// so downstream consumers can filter by it.
+    fn properties_for(&self, data: &Value) -> BasicProperties {
+        let properties = self.output_config.publish().properties().clone();
 
-                return;
-            }
+        let tenant = match data.as_object().and_then(tenant::tenant_of) {
+            Some(tenant) => tenant,
+            None => return properties,
         };
 
+        let mut headers = properties.headers().clone().unwrap_or_default();
+        headers.insert(
+            ShortString::from(tenant::TENANT_KEY),
+            AMQPValue::LongString(tenant.into()),
+        );
+
+        properties.with_headers(headers)
+    }
+}
+
+impl AmqpOutputElement {
+    /// Publishes the received data until the receiver is closed and drained.
+    /// Whenever the channel has been closed, the data is published again through a new one.
+    /// The queue must have been declared beforehand, see [AmqpOutputElement::try_declare_queue].
+    pub async fn run(
+        self,
+        mut channel: Arc<Channel>,
+        connection_supervisor: Arc<ConnectionSupervisor>,
+        mut receiver: Receiver<Value>,
+    ) -> Result<(), Error> {
+        let mut reconnected = false;
+
         loop {
             let data = match receiver.recv().await {
                 Some(data) => data,
-                None => {
-                    log::info!("received none from receiver");
-                    continue;
-                }
+                // The router has stopped and every pending output has been published.
+                None => break,
             };
 
             let payload = match serde_json::to_vec(&data) {
@@ -76,23 +90,30 @@ impl AmqpOutputElement {
                 }
             };
 
-            match channel
-                .basic_publish(
-                    self.output_config.publish().exchange(),
-                    queue.name(),
-                    *self.output_config.publish().options(),
-                    payload.as_slice(),
-                    self.output_config
-                        .publish()
-                        .properties()
-                        .clone(),
-                )
-                .await
-            {
-                Ok(_) => (),
-                Err(error) => {
-                    handle_error(format!("failed to publish to queue: {}", error), &self.state_tracker).await;
-                    continue;
+            let properties = self.properties_for(&data);
+
+            loop {
+                match channel
+                    .basic_publish(
+                        self.output_config.publish().exchange(),
+                        self.output_config.queue().name(),
+                        *self.output_config.publish().options(),
+                        payload.as_slice(),
+                        properties.clone(),
+                    )
+                    .await
+                {
+                    Ok(_) => break,
+                    Err(error) => {
+                        handle_error(format!("failed to publish to queue: {}", error), &self.state_tracker).await;
+
+                        if channel.status().connected() {
+                            break;
+                        }
+
+                        channel = self.reconnect(&connection_supervisor).await;
+                        reconnected = true;
+                    }
                 }
             }
 
@@ -101,6 +122,68 @@ impl AmqpOutputElement {
                 Err(error) => log::warn!("failed to send valid state to state tracker: {}", error),
             }
         }
+
+        // The initial channel is shared with the router, which closes it.
+        if reconnected {
+            if let Err(error) = shutdown::close_channel(&channel).await {
+                log::warn!("failed to close channel of output element '{}': {}", self.name, error);
+            }
+        }
+
+        match self.state_tracker.send_state(State::Idle).await {
+            Ok(_) => (),
+            Err(error) => log::warn!("failed to send idle state to state tracker: {}", error),
+        }
+
+        Ok(())
+    }
+
+    pub async fn try_declare_queue(&self, channel: &Channel) -> Result<(), Error> {
+        let queue = self.output_config.queue();
+
+        match channel
+            .queue_declare(
+                queue.name(),
+                *queue.declare().options(),
+                queue.declare().arguments().clone(),
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Error::new(
+                ErrorKind::AmqpFailure,
+                format!(
+                    "failed to declare queue for output element '{}': '{}'",
+                    self.name, error
+                ),
+            )),
+        }
+    }
+
+    /// Retries until the queue has been declared through a new channel.
+    async fn reconnect(&self, connection_supervisor: &ConnectionSupervisor) -> Arc<Channel> {
+        let mut backoff = connection_supervisor.backoff();
+
+        loop {
+            backoff.wait().await;
+
+            let channel = match connection_supervisor.try_get_channel().await {
+                Ok(channel) => channel,
+                Err(error) => {
+                    handle_error(format!("output element '{}' failed to reconnect: {}", self.name, error), &self.state_tracker).await;
+                    continue;
+                }
+            };
+
+            match self.try_declare_queue(&channel).await {
+                Ok(()) => {
+                    log::info!("output element '{}' has reconnected", self.name);
+
+                    return channel;
+                }
+                Err(error) => handle_error(error.message, &self.state_tracker).await,
+            }
+        }
     }
 }
 
@@ -113,4 +196,69 @@ async fn handle_error(error_message: String, state_tracker: &StateTrackerClient)
             log::warn!("failed to send error state to state tracker: '{}'", error);
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::sync::mpsc;
+    use tokio::time::Duration;
+
+    use crate::test_support;
+    use crate::test_support::fake_broker::{self, FakeBroker};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn publishes_through_new_channel_once_disconnected() {
+        let broker = FakeBroker::start().await;
+        let connection_supervisor = broker.connection_supervisor();
+        let channel = connection_supervisor.try_get_channel().await.unwrap();
+        let element = AmqpOutputElement::new(
+            "outputs".to_string(),
+            test_support::output_config("outputs"),
+            test_support::state_tracker_client().await,
+        );
+        let (sender, receiver) = mpsc::channel(8);
+
+        let running_element = tokio::spawn(element.run(
+            channel.clone(),
+            connection_supervisor,
+            receiver,
+        ));
+
+        sender.send(json!({ "id": 1 })).await.unwrap();
+        fake_broker::wait_until("the first output is published", || {
+            broker.publications().len() == 1
+        })
+        .await;
+
+        broker.set_refusing(true);
+        broker.disconnect();
+        fake_broker::wait_until("the channel is closed", || !channel.status().connected()).await;
+
+        sender.send(json!({ "id": 2 })).await.unwrap();
+        fake_broker::wait_until("reconnecting is retried", || {
+            broker.refused_connections() >= 2
+        })
+        .await;
+        broker.set_refusing(false);
+
+        fake_broker::wait_until("the second output is published", || {
+            broker.publications().len() == 2
+        })
+        .await;
+
+        let publication = &broker.publications()[1];
+        assert_eq!(publication.exchange, "");
+        assert_eq!(publication.routing_key, "outputs");
+        assert_eq!(publication.data, serde_json::to_vec(&json!({ "id": 2 })).unwrap());
+        assert_eq!(broker.connections(), 2);
+
+        drop(sender);
+        tokio::time::timeout(Duration::from_secs(5), running_element)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+}
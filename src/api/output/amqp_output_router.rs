@@ -3,62 +3,188 @@ use std::{collections::HashMap, sync::Arc};
 use lapin::Channel;
 use serde_json::Value;
 use tokio::sync::mpsc::{Receiver, Sender};
+use crate::api::connection_supervisor::ConnectionSupervisor;
 use crate::api::output::amqp_output_element::AmqpOutputElement;
+use crate::api::shutdown;
+use crate::api::shutdown::{ApiHandle, ShutdownSignal};
+use crate::error::Error;
 
 pub struct AmqpOutputRouter {
+    channel: Arc<Channel>,
     receiver: Receiver<(String, Value)>,
     output_senders: HashMap<String, Sender<Value>>,
+    /// Closed once every output element has stopped, each of them holding a sender.
+    stopped_elements: Receiver<()>,
 }
 
 impl AmqpOutputRouter {
-    pub fn new(
+    /// Every output queue is declared before any output element is spawned.
+    /// Output elements are spawned as outputs of the api, so their failures are fatal.
+    pub async fn try_new(
         channel: Arc<Channel>,
+        connection_supervisor: Arc<ConnectionSupervisor>,
         elements: Vec<AmqpOutputElement>,
         receiver: Receiver<(String, Value)>,
-    ) -> AmqpOutputRouter {
+        api_handle: &mut ApiHandle,
+    ) -> Result<AmqpOutputRouter, Error> {
+        for element in elements.iter() {
+            element.try_declare_queue(&channel).await?;
+        }
+
         let mut output_senders = HashMap::new();
-        let channel = channel;
+        let (running_sender, stopped_elements) = tokio::sync::mpsc::channel(1);
 
         for element in elements {
             let (sender, receiver) = tokio::sync::mpsc::channel(1024);
-            output_senders.insert(element.name().to_string(), sender);
-            tokio::spawn(element.run(channel.clone(), receiver));
+            let name = element.name().to_string();
+            output_senders.insert(name.clone(), sender);
+
+            let running_sender = running_sender.clone();
+            let running_element =
+                element.run(channel.clone(), connection_supervisor.clone(), receiver);
+
+            api_handle.spawn_output(name, async move {
+                let result = running_element.await;
+                drop(running_sender);
+
+                result
+            });
         }
 
-        AmqpOutputRouter {
+        Ok(AmqpOutputRouter {
+            channel,
             receiver,
             output_senders,
-        }
+            stopped_elements,
+        })
     }
 
-    pub async fn run(mut self) {
+    /// Routes outputs until the shutdown has been requested or every sender has been dropped.
+    /// Afterwards the pending outputs are flushed before closing the channel.
+    pub async fn run(mut self, mut shutdown_signal: ShutdownSignal) -> Result<(), Error> {
         loop {
-            let element_and_data = match self.receiver.recv().await {
-                Some(delivery) => delivery,
-                None => {
-                    log::info!("received none from receiver");
-                    continue;
-                }
+            let element_and_data = tokio::select! {
+                element_and_data = self.receiver.recv() => element_and_data,
+                _ = shutdown_signal.requested() => break,
             };
 
-            let output_sender = match self.output_senders.get(&element_and_data.0) {
-                Some(config) => config,
-                None => {
-                    log::error!("missing output element for '{}'", element_and_data.0);
-                    continue;
-                }
-            };
+            match element_and_data {
+                Some(element_and_data) => self.route(element_and_data).await,
+                None => break,
+            }
+        }
+
+        self.flush().await
+    }
+
+    async fn route(&self, element_and_data: (String, Value)) {
+        let output_sender = match self.output_senders.get(&element_and_data.0) {
+            Some(config) => config,
+            None => {
+                log::error!("missing output element for '{}'", element_and_data.0);
+                return;
+            }
+        };
 
-            match output_sender.send(element_and_data.1).await {
-                Ok(_) => (),
-                Err(error) => {
-                    log::error!(
-                        "failed to send data to output element '{}': '{}'",
-                        element_and_data.0,
-                        error
-                    );
-                }
+        match output_sender.send(element_and_data.1).await {
+            Ok(_) => (),
+            Err(error) => {
+                log::error!(
+                    "failed to send data to output element '{}': '{}'",
+                    element_and_data.0,
+                    error
+                );
             }
         }
     }
+
+    async fn flush(mut self) -> Result<(), Error> {
+        self.receiver.close();
+
+        while let Some(element_and_data) = self.receiver.recv().await {
+            self.route(element_and_data).await;
+        }
+
+        // Output elements stop once their pending outputs have been published.
+        self.output_senders.clear();
+
+        while self.stopped_elements.recv().await.is_some() {}
+
+        // Closed along with its connection, whenever the output elements have reconnected.
+        if !self.channel.status().connected() {
+            return Ok(());
+        }
+
+        shutdown::close_channel(&self.channel).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    use crate::test_support;
+    use crate::test_support::fake_broker::FakeBroker;
+
+    use super::*;
+
+    async fn output_router(
+        broker: &FakeBroker,
+        receiver: Receiver<(String, Value)>,
+        api_handle: &mut ApiHandle,
+    ) -> AmqpOutputRouter {
+        let connection_supervisor = broker.connection_supervisor();
+        let channel = connection_supervisor.try_get_channel().await.unwrap();
+        let element = AmqpOutputElement::new(
+            "outputs".to_string(),
+            test_support::output_config("outputs"),
+            test_support::state_tracker_client().await,
+        );
+
+        AmqpOutputRouter::try_new(
+            channel,
+            connection_supervisor,
+            vec![element],
+            receiver,
+            api_handle,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reports_output_elements_stopping_before_shutdown() {
+        let broker = FakeBroker::start().await;
+        let mut api_handle = ApiHandle::new(test_support::state_tracker_client().await);
+        let (_sender, receiver) = tokio::sync::mpsc::channel(8);
+
+        let output_router = output_router(&broker, receiver, &mut api_handle).await;
+        drop(output_router);
+
+        let error = tokio::time::timeout(Duration::from_secs(5), api_handle.fatal_errors().wait())
+            .await
+            .unwrap();
+
+        assert_eq!(error.message, "'outputs' has stopped unexpectedly");
+    }
+
+    #[tokio::test]
+    async fn publishes_pending_outputs_on_shutdown() {
+        let broker = FakeBroker::start().await;
+        let mut api_handle = ApiHandle::new(test_support::state_tracker_client().await);
+        let (sender, receiver) = tokio::sync::mpsc::channel(8);
+
+        let output_router = output_router(&broker, receiver, &mut api_handle).await;
+        let output_signal = api_handle.output_signal();
+        api_handle.spawn_output("output_router".to_string(), output_router.run(output_signal));
+
+        sender
+            .send(("outputs".to_string(), json!({ "id": 1 })))
+            .await
+            .unwrap();
+        api_handle.shutdown(Duration::from_secs(5)).await.unwrap();
+
+        assert_eq!(broker.publications().len(), 1);
+    }
 }
@@ -0,0 +1,257 @@
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+
+use cooplan_state_tracker::state::State;
+use cooplan_state_tracker::state_tracker_client::StateTrackerClient;
+use futures_util::FutureExt;
+use lapin::Channel;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+
+use crate::error::{Error, ErrorKind};
+
+const CHANNEL_CLOSE_REPLY_CODE: u16 = 200;
+
+/// Listened to by the running components in order to know when to stop.
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    deadline_receiver: watch::Receiver<Option<Instant>>,
+}
+
+impl ShutdownSignal {
+    /// Resolves with the shutdown deadline once the shutdown has been requested.
+    /// Never resolves whenever the [ApiHandle] has been dropped without shutting down.
+    pub async fn requested(&mut self) -> Instant {
+        loop {
+            if let Some(deadline) = *self.deadline_receiver.borrow_and_update() {
+                return deadline;
+            }
+
+            if self.deadline_receiver.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.deadline_receiver.borrow().is_some()
+    }
+}
+
+/// Notified of the first component failing while the api is running, after which
+/// the api should be shut down.
+#[derive(Clone)]
+pub struct FatalErrorWatcher {
+    error_receiver: watch::Receiver<Option<Error>>,
+}
+
+impl FatalErrorWatcher {
+    /// Never resolves whenever the api has been shut down without failing.
+    pub async fn wait(&mut self) -> Error {
+        loop {
+            if let Some(error) = self.error_receiver.borrow_and_update().clone() {
+                return error;
+            }
+
+            if self.error_receiver.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// Group of components stopping together.
+struct ShutdownStage {
+    deadline_sender: watch::Sender<Option<Instant>>,
+    tasks: Vec<(String, JoinHandle<Result<(), Error>>)>,
+}
+
+impl ShutdownStage {
+    fn new() -> ShutdownStage {
+        let (deadline_sender, _) = watch::channel(None);
+
+        ShutdownStage {
+            deadline_sender,
+            tasks: Vec::new(),
+        }
+    }
+
+    fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal {
+            deadline_receiver: self.deadline_sender.subscribe(),
+        }
+    }
+
+    /// Returns the components which did not stop before the deadline.
+    async fn stop(self, deadline: Instant) -> Vec<String> {
+        self.deadline_sender.send_replace(Some(deadline));
+
+        let mut timed_out_components = Vec::new();
+
+        for (name, mut task) in self.tasks {
+            match tokio::time::timeout_at(deadline, &mut task).await {
+                Ok(Ok(Ok(()))) => log::info!("'{}' has been shut down", name),
+                Ok(Ok(Err(error))) => log::warn!("'{}' had stopped with an error: {}", name, error),
+                Ok(Err(error)) => log::warn!("'{}' had stopped unexpectedly: {}", name, error),
+                Err(_) => {
+                    task.abort();
+                    timed_out_components.push(name);
+                }
+            }
+        }
+
+        timed_out_components
+    }
+}
+
+/// Returned by [crate::api::init::initialize] in order to stop the api cleanly.
+///
+/// Inputs stop first: their consumers are cancelled and the in-flight requests
+/// are awaited. Afterwards the pending outputs are published.
+pub struct ApiHandle {
+    inputs: ShutdownStage,
+    outputs: ShutdownStage,
+    fatal_error_sender: Arc<watch::Sender<Option<Error>>>,
+    state_tracker_client: StateTrackerClient,
+}
+
+impl ApiHandle {
+    pub(crate) fn new(mut state_tracker_client: StateTrackerClient) -> ApiHandle {
+        state_tracker_client.set_id("shutdown".to_string());
+
+        let (fatal_error_sender, _) = watch::channel(None);
+
+        ApiHandle {
+            inputs: ShutdownStage::new(),
+            outputs: ShutdownStage::new(),
+            fatal_error_sender: Arc::new(fatal_error_sender),
+            state_tracker_client,
+        }
+    }
+
+    pub(crate) fn input_signal(&self) -> ShutdownSignal {
+        self.inputs.signal()
+    }
+
+    pub(crate) fn output_signal(&self) -> ShutdownSignal {
+        self.outputs.signal()
+    }
+
+    pub(crate) fn spawn_input(
+        &mut self,
+        name: String,
+        component: impl Future<Output = Result<(), Error>> + Send + 'static,
+    ) {
+        let task = self.spawn(name.clone(), self.inputs.signal(), component);
+        self.inputs.tasks.push((name, task));
+    }
+
+    pub(crate) fn spawn_output(
+        &mut self,
+        name: String,
+        component: impl Future<Output = Result<(), Error>> + Send + 'static,
+    ) {
+        let task = self.spawn(name.clone(), self.outputs.signal(), component);
+        self.outputs.tasks.push((name, task));
+    }
+
+    /// Components stopping before the shutdown, either failing or panicking, are fatal errors.
+    fn spawn(
+        &self,
+        name: String,
+        shutdown_signal: ShutdownSignal,
+        component: impl Future<Output = Result<(), Error>> + Send + 'static,
+    ) -> JoinHandle<Result<(), Error>> {
+        let fatal_error_sender = self.fatal_error_sender.clone();
+        let state_tracker_client = self.state_tracker_client.clone();
+
+        tokio::spawn(async move {
+            let result = match AssertUnwindSafe(component).catch_unwind().await {
+                Ok(result) => result,
+                Err(_) => Err(Error::new(ErrorKind::InternalFailure, "panicked")),
+            };
+
+            if shutdown_signal.is_requested() {
+                return result;
+            }
+
+            let error = match &result {
+                Ok(()) => Error::new(
+                    ErrorKind::InternalFailure,
+                    format!("'{}' has stopped unexpectedly", name),
+                ),
+                Err(error) => Error::new(error.kind(), format!("'{}' has failed: {}", name, error)),
+            };
+
+            log::error!("{}", error);
+            send_state(&state_tracker_client, State::Error(error.message.clone())).await;
+
+            fatal_error_sender.send_if_modified(|fatal_error| {
+                if fatal_error.is_some() {
+                    return false;
+                }
+
+                *fatal_error = Some(error);
+                true
+            });
+
+            result
+        })
+    }
+
+    pub fn fatal_errors(&self) -> FatalErrorWatcher {
+        FatalErrorWatcher {
+            error_receiver: self.fatal_error_sender.subscribe(),
+        }
+    }
+
+    /// Components still running once the timeout elapses are aborted.
+    pub async fn shutdown(self, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+
+        log::info!("shutting down inputs");
+        let mut timed_out_components = self.inputs.stop(deadline).await;
+
+        log::info!("flushing outputs");
+        timed_out_components.extend(self.outputs.stop(deadline).await);
+
+        if timed_out_components.is_empty() {
+            log::info!("api has been shut down");
+            send_state(&self.state_tracker_client, State::Idle).await;
+
+            return Ok(());
+        }
+
+        let error_message = format!(
+            "components {:?} did not shut down before the deadline",
+            timed_out_components
+        );
+        send_state(
+            &self.state_tracker_client,
+            State::Error(error_message.clone()),
+        )
+        .await;
+
+        Err(Error::new(ErrorKind::ShutdownFailure, error_message))
+    }
+}
+
+/// Closes a channel once the component using it has stopped.
+pub(crate) async fn close_channel(channel: &Channel) -> Result<(), Error> {
+    match channel.close(CHANNEL_CLOSE_REPLY_CODE, "shutdown").await {
+        Ok(()) => Ok(()),
+        Err(error) => Err(Error::new(
+            ErrorKind::AmqpFailure,
+            format!("failed to close channel: {}", error),
+        )),
+    }
+}
+
+async fn send_state(state_tracker_client: &StateTrackerClient, state: State) {
+    match state_tracker_client.send_state(state).await {
+        Ok(_) => (),
+        Err(error) => log::warn!("failed to send state: {}", error),
+    }
+}
@@ -0,0 +1,10 @@
+use serde_json::{Map, Value};
+
+use crate::api::input::token::ORGANIZATION_ID_CLAIM;
+
+/// Key identifying the tenant within requests, output data and output headers.
+pub const TENANT_KEY: &str = ORGANIZATION_ID_CLAIM;
+
+pub fn tenant_of(data: &Map<String, Value>) -> Option<&str> {
+    data.get(TENANT_KEY).and_then(Value::as_str)
+}
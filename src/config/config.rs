@@ -2,12 +2,24 @@ use cooplan_lapin_wrapper::config::amqp_connect_config::AmqpConnectConfig;
 use serde::{Deserialize};
 
 use crate::config::openid_connect_config::OpenIdConnectConfig;
+use crate::config::reconnection_config::ReconnectionConfig;
+use crate::config::revocation_config::RevocationConfig;
+use crate::config::roles_config::RolesConfig;
+use crate::config::scopes_config::ScopesConfig;
 use crate::error::{Error, ErrorKind};
 
 #[derive(Deserialize)]
 pub struct Config {
     pub openid_connect: OpenIdConnectConfig,
-    pub amqp_connect_config: AmqpConnectConfig
+    pub amqp_connect_config: AmqpConnectConfig,
+    #[serde(default)]
+    pub revocation: Option<RevocationConfig>,
+    #[serde(default)]
+    pub roles: Option<RolesConfig>,
+    #[serde(default)]
+    pub scopes: Option<ScopesConfig>,
+    #[serde(default)]
+    pub reconnection: ReconnectionConfig,
 }
 
 pub async fn try_read_config(config_file: &str) -> Result<Config, Error> {
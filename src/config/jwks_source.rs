@@ -0,0 +1,163 @@
+use std::time::SystemTime;
+
+use jsonwebtoken::jwk::JwkSet;
+use serde::{Deserialize, Serialize};
+
+use crate::config::token_validator_config;
+use crate::error::{Error, ErrorKind};
+
+const DEFAULT_FILE_POLL_INTERVAL_IN_SECONDS: u64 = 5;
+
+/// Where the jwks is loaded from, both at startup and whenever it is refreshed.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JwksSource {
+    /// Requests the jwks from the configured or discovered jwks uri.
+    #[default]
+    Http,
+    /// Reads the jwks from a file, which is read again whenever it is modified.
+    File {
+        path: String,
+        #[serde(default = "default_file_poll_interval_in_seconds")]
+        poll_interval_in_seconds: u64,
+    },
+    /// Jwks written directly within the configuration.
+    Inline { jwks: JwkSet },
+}
+
+impl JwksSource {
+    pub fn is_http(&self) -> bool {
+        matches!(self, JwksSource::Http)
+    }
+
+    pub async fn try_load(&self, jwks_uri: &str) -> Result<JwkSet, Error> {
+        match self {
+            JwksSource::Http => token_validator_config::try_get_jwks(jwks_uri).await,
+            JwksSource::File { path, .. } => try_read_jwks(path.as_str()).await,
+            JwksSource::Inline { jwks } => Ok(jwks.clone()),
+        }
+    }
+}
+
+async fn try_read_jwks(path: &str) -> Result<JwkSet, Error> {
+    let jwks = match tokio::fs::read_to_string(path).await {
+        Ok(jwks) => match serde_json::from_str::<JwkSet>(jwks.as_str()) {
+            Ok(jwks) => jwks,
+            Err(error) => {
+                return Err(Error::new(
+                    ErrorKind::AutoConfigFailure,
+                    format!("failed to deserialize jwks file's content: {}", error),
+                ));
+            }
+        },
+        Err(error) => {
+            return Err(Error::new(
+                ErrorKind::AutoConfigFailure,
+                format!("failed to read jwks file: {}", error),
+            ));
+        }
+    };
+
+    Ok(jwks)
+}
+
+pub(crate) async fn try_get_modification_time(path: &str) -> Result<SystemTime, Error> {
+    let metadata = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(error) => {
+            return Err(Error::new(
+                ErrorKind::AutoConfigFailure,
+                format!("failed to read jwks file's metadata: {}", error),
+            ));
+        }
+    };
+
+    match metadata.modified() {
+        Ok(modification_time) => Ok(modification_time),
+        Err(error) => Err(Error::new(
+            ErrorKind::AutoConfigFailure,
+            format!("failed to read jwks file's modification time: {}", error),
+        )),
+    }
+}
+
+fn default_file_poll_interval_in_seconds() -> u64 {
+    DEFAULT_FILE_POLL_INTERVAL_IN_SECONDS
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::test_support::TemporaryFile;
+
+    use super::*;
+
+    fn jwks() -> JwkSet {
+        serde_json::from_value(json!({
+            "keys": [{ "kty": "oct", "kid": "key", "k": "a2V5" }]
+        }))
+        .unwrap()
+    }
+
+    fn file_source(path: &str) -> JwksSource {
+        JwksSource::File {
+            path: path.to_string(),
+            poll_interval_in_seconds: DEFAULT_FILE_POLL_INTERVAL_IN_SECONDS,
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_inline_jwks() {
+        let jwks = JwksSource::Inline { jwks: jwks() }.try_load("").await.unwrap();
+
+        assert!(jwks.find("key").is_some());
+    }
+
+    #[tokio::test]
+    async fn loads_jwks_from_file() {
+        let file = TemporaryFile::new(serde_json::to_string(&jwks()).unwrap().as_str());
+
+        let jwks = file_source(file.path()).try_load("").await.unwrap();
+
+        assert!(jwks.find("key").is_some());
+    }
+
+    #[tokio::test]
+    async fn fails_to_load_invalid_jwks_file() {
+        let file = TemporaryFile::new("not a jwks");
+
+        let error = file_source(file.path()).try_load("").await.err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::AutoConfigFailure);
+    }
+
+    #[tokio::test]
+    async fn fails_to_load_missing_jwks_file() {
+        let path = {
+            let file = TemporaryFile::new("");
+            file.path().to_string()
+        };
+
+        let error = file_source(path.as_str()).try_load("").await.err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::AutoConfigFailure);
+    }
+
+    #[test]
+    fn defaults_poll_interval_of_file_source() {
+        let source: JwksSource =
+            serde_json::from_value(json!({ "type": "file", "path": "jwks.json" })).unwrap();
+
+        match source {
+            JwksSource::File {
+                poll_interval_in_seconds,
+                ..
+            } => assert_eq!(
+                poll_interval_in_seconds,
+                DEFAULT_FILE_POLL_INTERVAL_IN_SECONDS
+            ),
+            _ => panic!("expected a file source"),
+        }
+    }
+}
@@ -1,3 +1,10 @@
+#[allow(clippy::module_inception)]
 pub mod config;
+pub mod jwks_source;
 pub mod token_validator_config;
-pub mod openid_connect_config;
\ No newline at end of file
+pub mod openid_connect_config;
+pub mod permission_mappings;
+pub mod reconnection_config;
+pub mod revocation_config;
+pub mod roles_config;
+pub mod scopes_config;
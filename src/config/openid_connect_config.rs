@@ -1,10 +1,54 @@
+use jsonwebtoken::Algorithm;
 use serde::{Deserialize, Serialize};
 
+use crate::config::jwks_source::JwksSource;
+
+const DEFAULT_JWKS_REFRESH_INTERVAL_IN_SECONDS: u64 = 3600;
+const DEFAULT_JWKS_MIN_REFRESH_INTERVAL_IN_SECONDS: u64 = 30;
+const DEFAULT_LEEWAY_IN_SECONDS: u64 = 60;
+const DEFAULT_ALGORITHMS: [Algorithm; 6] = [
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+];
+
 #[derive(Serialize, Deserialize)]
 pub struct OpenIdConnectConfig {
+    /// Issuer whose discovery document is used for filling in the jwks uri and the
+    /// allowed algorithms whenever they are not explicitly configured.
+    #[serde(default)]
+    discovery_issuer: Option<String>,
+    #[serde(default)]
+    jwks_source: JwksSource,
+    #[serde(default)]
     jwks_uri: String,
+    #[serde(default)]
     issuers: Vec<String>,
     audience: Vec<String>,
+    /// Interval between background refreshes of the jwks. Zero disables them.
+    #[serde(default = "default_jwks_refresh_interval_in_seconds")]
+    jwks_refresh_interval_in_seconds: u64,
+    /// Minimum time between two refreshes of the jwks triggered by an unknown kid.
+    #[serde(default = "default_jwks_min_refresh_interval_in_seconds")]
+    jwks_min_refresh_interval_in_seconds: u64,
+    /// Algorithms which tokens are allowed to be signed with, so tokens cannot
+    /// downgrade to a weaker algorithm.
+    #[serde(default)]
+    algorithms: Option<Vec<Algorithm>>,
+    /// Clock skew tolerated when validating the time based claims.
+    #[serde(default = "default_leeway_in_seconds")]
+    leeway_in_seconds: u64,
+    #[serde(default)]
+    validate_nbf: bool,
+    /// Maximum time since the token has been issued, requires the 'iat' claim.
+    #[serde(default)]
+    max_token_age_in_seconds: Option<u64>,
+    /// Claims which must be present in the token besides 'exp'.
+    #[serde(default)]
+    required_claims: Vec<String>,
 }
 
 impl OpenIdConnectConfig {
@@ -14,21 +58,140 @@ impl OpenIdConnectConfig {
         audience: Vec<String>,
     ) -> OpenIdConnectConfig {
         OpenIdConnectConfig {
+            discovery_issuer: None,
+            jwks_source: JwksSource::default(),
             jwks_uri,
             issuers,
             audience,
+            jwks_refresh_interval_in_seconds: DEFAULT_JWKS_REFRESH_INTERVAL_IN_SECONDS,
+            jwks_min_refresh_interval_in_seconds: DEFAULT_JWKS_MIN_REFRESH_INTERVAL_IN_SECONDS,
+            algorithms: None,
+            leeway_in_seconds: DEFAULT_LEEWAY_IN_SECONDS,
+            validate_nbf: false,
+            max_token_age_in_seconds: None,
+            required_claims: Vec::new(),
         }
     }
 
+    /// Configuration whose jwks uri, issuers and allowed algorithms are discovered
+    /// from the issuer's openid configuration.
+    pub fn from_discovery_issuer(
+        discovery_issuer: String,
+        audience: Vec<String>,
+    ) -> OpenIdConnectConfig {
+        let mut openid_connect = OpenIdConnectConfig::new(String::new(), Vec::new(), audience);
+        openid_connect.discovery_issuer = Some(discovery_issuer);
+
+        openid_connect
+    }
+
+    pub fn discovery_issuer(&self) -> Option<&str> {
+        self.discovery_issuer.as_deref()
+    }
+
+    pub fn jwks_source(&self) -> &JwksSource {
+        &self.jwks_source
+    }
+
+    pub fn set_jwks_source(&mut self, jwks_source: JwksSource) {
+        self.jwks_source = jwks_source;
+    }
+
     pub fn jwks_uri(&self) -> &str {
         self.jwks_uri.as_str()
     }
 
+    pub fn set_jwks_uri(&mut self, jwks_uri: String) {
+        self.jwks_uri = jwks_uri;
+    }
+
     pub fn issuers(&self) -> &[String] {
         self.issuers.as_slice()
     }
 
+    pub fn add_issuer(&mut self, issuer: String) {
+        if !self.issuers.contains(&issuer) {
+            self.issuers.push(issuer);
+        }
+    }
+
     pub fn audience(&self) -> &[String] {
         self.audience.as_slice()
     }
+
+    pub fn jwks_refresh_interval_in_seconds(&self) -> u64 {
+        self.jwks_refresh_interval_in_seconds
+    }
+
+    pub fn set_jwks_refresh_interval_in_seconds(&mut self, interval: u64) {
+        self.jwks_refresh_interval_in_seconds = interval;
+    }
+
+    pub fn jwks_min_refresh_interval_in_seconds(&self) -> u64 {
+        self.jwks_min_refresh_interval_in_seconds
+    }
+
+    pub fn set_jwks_min_refresh_interval_in_seconds(&mut self, interval: u64) {
+        self.jwks_min_refresh_interval_in_seconds = interval;
+    }
+
+    /// Defaults to the RSA algorithms whenever none have been configured nor discovered.
+    pub fn algorithms(&self) -> &[Algorithm] {
+        match &self.algorithms {
+            Some(algorithms) => algorithms.as_slice(),
+            None => &DEFAULT_ALGORITHMS,
+        }
+    }
+
+    pub fn has_algorithms(&self) -> bool {
+        self.algorithms.is_some()
+    }
+
+    pub fn set_algorithms(&mut self, algorithms: Vec<Algorithm>) {
+        self.algorithms = Some(algorithms);
+    }
+
+    pub fn leeway_in_seconds(&self) -> u64 {
+        self.leeway_in_seconds
+    }
+
+    pub fn set_leeway_in_seconds(&mut self, leeway: u64) {
+        self.leeway_in_seconds = leeway;
+    }
+
+    pub fn validate_nbf(&self) -> bool {
+        self.validate_nbf
+    }
+
+    pub fn set_validate_nbf(&mut self, validate_nbf: bool) {
+        self.validate_nbf = validate_nbf;
+    }
+
+    pub fn max_token_age_in_seconds(&self) -> Option<u64> {
+        self.max_token_age_in_seconds
+    }
+
+    pub fn set_max_token_age_in_seconds(&mut self, max_token_age: Option<u64>) {
+        self.max_token_age_in_seconds = max_token_age;
+    }
+
+    pub fn required_claims(&self) -> &[String] {
+        self.required_claims.as_slice()
+    }
+
+    pub fn set_required_claims(&mut self, required_claims: Vec<String>) {
+        self.required_claims = required_claims;
+    }
+}
+
+fn default_jwks_refresh_interval_in_seconds() -> u64 {
+    DEFAULT_JWKS_REFRESH_INTERVAL_IN_SECONDS
+}
+
+fn default_jwks_min_refresh_interval_in_seconds() -> u64 {
+    DEFAULT_JWKS_MIN_REFRESH_INTERVAL_IN_SECONDS
+}
+
+fn default_leeway_in_seconds() -> u64 {
+    DEFAULT_LEEWAY_IN_SECONDS
 }
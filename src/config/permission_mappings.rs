@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_json::Value;
+
+use crate::api::input::permission_set::PermissionSet;
+use crate::config::roles_config::RolesConfig;
+use crate::config::scopes_config::ScopesConfig;
+
+/// Sources of permissions besides the token's permissions claims.
+/// The permissions of every role and scope are built into a set once, which is
+/// shared by every token having that role or scope.
+#[derive(Clone, Default)]
+pub struct PermissionMappings {
+    roles: Option<RolesConfig>,
+    scopes: Option<ScopesConfig>,
+    permission_sets_per_role: HashMap<String, Arc<PermissionSet>>,
+    permission_sets_per_scope: HashMap<String, Arc<PermissionSet>>,
+}
+
+impl PermissionMappings {
+    pub fn new(roles: Option<RolesConfig>, scopes: Option<ScopesConfig>) -> PermissionMappings {
+        let permission_sets_per_role = match &roles {
+            Some(roles) => build_permission_sets(roles.permissions()),
+            None => HashMap::new(),
+        };
+
+        let permission_sets_per_scope = match &scopes {
+            Some(scopes) => build_permission_sets(scopes.permissions()),
+            None => HashMap::new(),
+        };
+
+        PermissionMappings {
+            roles,
+            scopes,
+            permission_sets_per_role,
+            permission_sets_per_scope,
+        }
+    }
+
+    pub fn roles(&self) -> Option<&RolesConfig> {
+        self.roles.as_ref()
+    }
+
+    pub fn scopes(&self) -> Option<&ScopesConfig> {
+        self.scopes.as_ref()
+    }
+
+    /// Whenever there is a fallback, tokens without permissions claims are not malformed.
+    pub fn has_fallback(&self) -> bool {
+        self.roles.is_some() || self.scopes.is_some()
+    }
+
+    pub fn expand(&self, claims: &HashMap<String, Value>) -> Vec<&str> {
+        let mut permissions = Vec::new();
+
+        if let Some(roles) = &self.roles {
+            permissions.extend(roles.expand(claims));
+        }
+
+        if let Some(scopes) = &self.scopes {
+            permissions.extend(scopes.expand(claims));
+        }
+
+        permissions
+    }
+
+    /// Prebuilt permission sets of every known role and scope found within the claims.
+    pub fn permission_sets(&self, claims: &HashMap<String, Value>) -> Vec<Arc<PermissionSet>> {
+        let mut permission_sets = Vec::new();
+
+        if let Some(roles) = &self.roles {
+            permission_sets.extend(
+                roles
+                    .roles_of(claims)
+                    .into_iter()
+                    .filter_map(|role| self.permission_sets_per_role.get(role))
+                    .cloned(),
+            );
+        }
+
+        if let Some(scopes) = &self.scopes {
+            permission_sets.extend(
+                scopes
+                    .scopes_of(claims)
+                    .into_iter()
+                    .filter_map(|scope| self.permission_sets_per_scope.get(scope))
+                    .cloned(),
+            );
+        }
+
+        permission_sets
+    }
+}
+
+fn build_permission_sets(
+    permissions: &HashMap<String, Vec<String>>,
+) -> HashMap<String, Arc<PermissionSet>> {
+    permissions
+        .iter()
+        .map(|(name, permissions)| {
+            (
+                name.clone(),
+                Arc::new(PermissionSet::from_permissions(permissions)),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn permission_mappings() -> PermissionMappings {
+        PermissionMappings::new(
+            Some(RolesConfig::new(
+                "realm_access.roles".to_string(),
+                HashMap::from([("admin".to_string(), vec!["*:*".to_string()])]),
+            )),
+            Some(ScopesConfig::new(
+                "scope".to_string(),
+                HashMap::from([("items:read".to_string(), vec!["read:items".to_string()])]),
+            )),
+        )
+    }
+
+    fn claims(claims: serde_json::Value) -> HashMap<String, Value> {
+        serde_json::from_value(claims).unwrap()
+    }
+
+    #[test]
+    fn shares_prebuilt_permission_sets_between_tokens() {
+        let permission_mappings = permission_mappings();
+        let claims = claims(json!({"realm_access": {"roles": ["admin"]}}));
+
+        let first_permission_sets = permission_mappings.permission_sets(&claims);
+        let second_permission_sets = permission_mappings.permission_sets(&claims);
+
+        assert_eq!(first_permission_sets.len(), 1);
+        assert!(Arc::ptr_eq(&first_permission_sets[0], &second_permission_sets[0]));
+        assert!(first_permission_sets[0].contains("delete:anything"));
+    }
+
+    #[test]
+    fn finds_permission_sets_of_known_roles_and_scopes_only() {
+        let permission_sets = permission_mappings().permission_sets(&claims(json!({
+            "realm_access": {"roles": ["unknown"]},
+            "scope": "openid items:read",
+        })));
+
+        assert_eq!(permission_sets.len(), 1);
+        assert!(permission_sets[0].contains("read:items"));
+        assert!(!permission_sets[0].contains("write:items"));
+    }
+}
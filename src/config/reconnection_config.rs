@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_INITIAL_BACKOFF_IN_MILLISECONDS: u64 = 500;
+const DEFAULT_MAX_BACKOFF_IN_MILLISECONDS: u64 = 30000;
+
+/// Backoff between the attempts of reconnecting to the broker, which doubles
+/// after every failed attempt up to the max backoff. Attempts wait a random delay
+/// between half and the whole of the backoff.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ReconnectionConfig {
+    #[serde(default = "default_initial_backoff_in_milliseconds")]
+    initial_backoff_in_milliseconds: u64,
+    #[serde(default = "default_max_backoff_in_milliseconds")]
+    max_backoff_in_milliseconds: u64,
+}
+
+impl ReconnectionConfig {
+    pub fn new(
+        initial_backoff_in_milliseconds: u64,
+        max_backoff_in_milliseconds: u64,
+    ) -> ReconnectionConfig {
+        ReconnectionConfig {
+            initial_backoff_in_milliseconds,
+            max_backoff_in_milliseconds,
+        }
+    }
+
+    pub fn initial_backoff_in_milliseconds(&self) -> u64 {
+        self.initial_backoff_in_milliseconds
+    }
+
+    pub fn max_backoff_in_milliseconds(&self) -> u64 {
+        self.max_backoff_in_milliseconds
+    }
+}
+
+impl Default for ReconnectionConfig {
+    fn default() -> Self {
+        ReconnectionConfig::new(
+            DEFAULT_INITIAL_BACKOFF_IN_MILLISECONDS,
+            DEFAULT_MAX_BACKOFF_IN_MILLISECONDS,
+        )
+    }
+}
+
+fn default_initial_backoff_in_milliseconds() -> u64 {
+    DEFAULT_INITIAL_BACKOFF_IN_MILLISECONDS
+}
+
+fn default_max_backoff_in_milliseconds() -> u64 {
+    DEFAULT_MAX_BACKOFF_IN_MILLISECONDS
+}
@@ -0,0 +1,86 @@
+use cooplan_lapin_wrapper::config::amqp_queue_consumer::AmqpQueueConsumer;
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_REVOCATION_TTL_IN_SECONDS: u64 = 86400;
+const DEFAULT_REVOCATION_EXCHANGE: &str = "token_revocations";
+const DEFAULT_QUEUE_EXPIRY_IN_SECONDS: u64 = 86400;
+
+/// Control messages from which token revocations are consumed. Revocations are
+/// published to a fanout exchange, every instance consuming them from its own
+/// durable queue, so that all of them revoke the tokens.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RevocationConfig {
+    /// The queue's name, or the exchange's whenever empty, is suffixed with the
+    /// instance id in order to name each instance's queue.
+    queue_consumer: AmqpQueueConsumer,
+    #[serde(default = "default_revocation_exchange")]
+    exchange: String,
+    /// Keeps the instance's queue, along with the revocations published meanwhile,
+    /// across restarts. Defaults to an id generated per process, whose queue is
+    /// only kept across reconnections.
+    #[serde(default)]
+    instance_id: Option<String>,
+    /// Time the broker keeps an instance's queue once it is no longer consumed.
+    #[serde(default = "default_queue_expiry_in_seconds")]
+    queue_expiry_in_seconds: u64,
+    /// Time a revocation is kept whenever it does not specify when the revoked
+    /// tokens expire. Should be at least the lifetime of the tokens.
+    #[serde(default = "default_revocation_ttl_in_seconds")]
+    default_ttl_in_seconds: u64,
+}
+
+impl RevocationConfig {
+    pub fn new(queue_consumer: AmqpQueueConsumer, default_ttl_in_seconds: u64) -> RevocationConfig {
+        RevocationConfig {
+            queue_consumer,
+            exchange: DEFAULT_REVOCATION_EXCHANGE.to_string(),
+            instance_id: None,
+            queue_expiry_in_seconds: DEFAULT_QUEUE_EXPIRY_IN_SECONDS,
+            default_ttl_in_seconds,
+        }
+    }
+
+    pub fn queue_consumer(&self) -> &AmqpQueueConsumer {
+        &self.queue_consumer
+    }
+
+    pub fn exchange(&self) -> &str {
+        self.exchange.as_str()
+    }
+
+    pub fn set_exchange(&mut self, exchange: String) {
+        self.exchange = exchange;
+    }
+
+    pub fn instance_id(&self) -> Option<&str> {
+        self.instance_id.as_deref()
+    }
+
+    pub fn set_instance_id(&mut self, instance_id: String) {
+        self.instance_id = Some(instance_id);
+    }
+
+    pub fn queue_expiry_in_seconds(&self) -> u64 {
+        self.queue_expiry_in_seconds
+    }
+
+    pub fn set_queue_expiry_in_seconds(&mut self, queue_expiry_in_seconds: u64) {
+        self.queue_expiry_in_seconds = queue_expiry_in_seconds;
+    }
+
+    pub fn default_ttl_in_seconds(&self) -> u64 {
+        self.default_ttl_in_seconds
+    }
+}
+
+fn default_revocation_ttl_in_seconds() -> u64 {
+    DEFAULT_REVOCATION_TTL_IN_SECONDS
+}
+
+fn default_revocation_exchange() -> String {
+    DEFAULT_REVOCATION_EXCHANGE.to_string()
+}
+
+fn default_queue_expiry_in_seconds() -> u64 {
+    DEFAULT_QUEUE_EXPIRY_IN_SECONDS
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const CLAIM_PATH_SEPARATOR: char = '.';
+
+/// Maps the roles found within a token's claim into 'action:element' permissions.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RolesConfig {
+    /// Path to the claim containing the roles, nested claims being separated by '.',
+    /// e.g. 'realm_access.roles'.
+    claim: String,
+    permissions: HashMap<String, Vec<String>>,
+}
+
+impl RolesConfig {
+    pub fn new(claim: String, permissions: HashMap<String, Vec<String>>) -> RolesConfig {
+        RolesConfig { claim, permissions }
+    }
+
+    pub fn claim(&self) -> &str {
+        self.claim.as_str()
+    }
+
+    pub fn permissions(&self) -> &HashMap<String, Vec<String>> {
+        &self.permissions
+    }
+
+    /// Permissions of every known role found within the claims.
+    pub fn expand(&self, claims: &HashMap<String, Value>) -> Vec<&str> {
+        self.roles_of(claims)
+            .into_iter()
+            .filter_map(|role| self.permissions.get(role))
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Roles found within the claims, whether they are known or not.
+    pub fn roles_of<'claims>(&self, claims: &'claims HashMap<String, Value>) -> Vec<&'claims str> {
+        match find_claim(claims, self.claim.as_str()).and_then(Value::as_array) {
+            Some(roles) => roles.iter().filter_map(Value::as_str).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Claims whose name contains the separator, such as namespaced claims,
+/// are looked up as a whole before being treated as a path.
+fn find_claim<'claims>(
+    claims: &'claims HashMap<String, Value>,
+    path: &str,
+) -> Option<&'claims Value> {
+    if let Some(claim) = claims.get(path) {
+        return Some(claim);
+    }
+
+    let mut segments = path.split(CLAIM_PATH_SEPARATOR);
+    let mut claim = claims.get(segments.next()?)?;
+
+    for segment in segments {
+        claim = claim.get(segment)?;
+    }
+
+    Some(claim)
+}
@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const DEFAULT_SCOPE_CLAIM: &str = "scope";
+
+/// Maps the oauth2 scopes of a token into 'action:element' permissions.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScopesConfig {
+    /// Claim containing either a space separated string or an array of scopes.
+    #[serde(default = "default_scope_claim")]
+    claim: String,
+    permissions: HashMap<String, Vec<String>>,
+}
+
+impl ScopesConfig {
+    pub fn new(claim: String, permissions: HashMap<String, Vec<String>>) -> ScopesConfig {
+        ScopesConfig { claim, permissions }
+    }
+
+    pub fn claim(&self) -> &str {
+        self.claim.as_str()
+    }
+
+    pub fn permissions(&self) -> &HashMap<String, Vec<String>> {
+        &self.permissions
+    }
+
+    /// Permissions of every known scope found within the claims.
+    pub fn expand(&self, claims: &HashMap<String, Value>) -> Vec<&str> {
+        self.scopes_of(claims)
+            .into_iter()
+            .filter_map(|scope| self.permissions.get(scope))
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Scopes found within the claims, whether they are known or not.
+    pub fn scopes_of<'claims>(&self, claims: &'claims HashMap<String, Value>) -> Vec<&'claims str> {
+        match claims.get(self.claim.as_str()) {
+            Some(Value::String(scopes)) => scopes.split_whitespace().collect(),
+            Some(Value::Array(scopes)) => scopes.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn default_scope_claim() -> String {
+    DEFAULT_SCOPE_CLAIM.to_string()
+}
@@ -1,12 +1,19 @@
+use std::str::FromStr;
+use std::time::Duration;
+
 use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::Algorithm;
+use serde::Deserialize;
 
 use crate::error::{Error, ErrorKind};
 
 use super::openid_connect_config::OpenIdConnectConfig;
+use super::permission_mappings::PermissionMappings;
 
 pub struct TokenValidatorConfig {
     jwks: JwkSet,
     openid_connect: OpenIdConnectConfig,
+    permission_mappings: PermissionMappings,
 }
 
 impl TokenValidatorConfig {
@@ -14,6 +21,7 @@ impl TokenValidatorConfig {
         TokenValidatorConfig {
             jwks,
             openid_connect,
+            permission_mappings: PermissionMappings::default(),
         }
     }
 
@@ -24,22 +32,66 @@ impl TokenValidatorConfig {
     pub fn open_id_connect(&self) -> &OpenIdConnectConfig {
         &self.openid_connect
     }
+
+    pub fn permission_mappings(&self) -> &PermissionMappings {
+        &self.permission_mappings
+    }
+
+    pub fn set_permission_mappings(&mut self, permission_mappings: PermissionMappings) {
+        self.permission_mappings = permission_mappings;
+    }
+
+    pub fn owned_parts(self) -> (JwkSet, OpenIdConnectConfig, PermissionMappings) {
+        (self.jwks, self.openid_connect, self.permission_mappings)
+    }
 }
 
-pub async fn try_generate_config(openid_connect: OpenIdConnectConfig) -> Result<TokenValidatorConfig, Error> {
-    let jwks = match try_get_jwks(openid_connect.jwks_uri()).await {
+const OPENID_CONFIGURATION_PATH: &str = "/.well-known/openid-configuration";
+/// Keeps a hung identity provider from blocking the refreshes, and the requests
+/// waiting for them, indefinitely.
+const HTTP_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Subset of the openid provider metadata required for validating tokens.
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    jwks_uri: String,
+    #[serde(default)]
+    id_token_signing_alg_values_supported: Vec<String>,
+}
+
+pub async fn try_generate_config(mut openid_connect: OpenIdConnectConfig) -> Result<TokenValidatorConfig, Error> {
+    if let Some(discovery_issuer) = openid_connect.discovery_issuer() {
+        let provider_metadata = try_discover(discovery_issuer).await?;
+        apply_provider_metadata(&mut openid_connect, provider_metadata);
+    }
+
+    if openid_connect.jwks_source().is_http() && openid_connect.jwks_uri().is_empty() {
+        return Err(Error::new(
+            ErrorKind::AutoConfigFailure,
+            "jwks uri is neither configured nor discovered",
+        ));
+    }
+
+    let jwks = match openid_connect
+        .jwks_source()
+        .try_load(openid_connect.jwks_uri())
+        .await
+    {
         Ok(jwks) => jwks,
         Err(error) => return Err(error),
     };
 
-    Ok(TokenValidatorConfig {
-        jwks,
-        openid_connect,
-    })
+    Ok(TokenValidatorConfig::new(jwks, openid_connect))
 }
 
-async fn try_get_jwks(jwks_uri: &str) -> Result<JwkSet, Error> {
-    let jwks = match reqwest::get(jwks_uri).await {
+pub(crate) async fn try_get_jwks(jwks_uri: &str) -> Result<JwkSet, Error> {
+    let jwks = match try_build_http_client()?
+        .get(jwks_uri)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+    {
         Ok(response) => match response.json::<JwkSet>().await {
             Ok(jwks) => jwks,
             Err(error) => {
@@ -59,3 +111,245 @@ async fn try_get_jwks(jwks_uri: &str) -> Result<JwkSet, Error> {
 
     Ok(jwks)
 }
+
+async fn try_discover(discovery_issuer: &str) -> Result<ProviderMetadata, Error> {
+    let discovery_uri = format!(
+        "{}{}",
+        discovery_issuer.trim_end_matches('/'),
+        OPENID_CONFIGURATION_PATH
+    );
+
+    let provider_metadata = match try_build_http_client()?
+        .get(discovery_uri.as_str())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+    {
+        Ok(response) => match response.json::<ProviderMetadata>().await {
+            Ok(provider_metadata) => provider_metadata,
+            Err(error) => {
+                return Err(Error::new(
+                    ErrorKind::AutoConfigFailure,
+                    format!("failed to deserialize response as openid configuration: {}", error),
+                ));
+            }
+        },
+        Err(error) => {
+            return Err(Error::new(
+                ErrorKind::AutoConfigFailure,
+                format!("failed to request openid configuration: {}", error),
+            ));
+        }
+    };
+
+    // The issuer must be identical to the one used for the discovery, otherwise
+    // the openid configuration could be impersonating another issuer.
+    if provider_metadata.issuer != discovery_issuer {
+        return Err(Error::new(
+            ErrorKind::AutoConfigFailure,
+            format!(
+                "discovered issuer '{}' does not match '{}'",
+                provider_metadata.issuer, discovery_issuer
+            ),
+        ));
+    }
+
+    Ok(provider_metadata)
+}
+
+/// Explicitly configured values take precedence over the discovered ones.
+fn apply_provider_metadata(
+    openid_connect: &mut OpenIdConnectConfig,
+    provider_metadata: ProviderMetadata,
+) {
+    if openid_connect.jwks_uri().is_empty() {
+        openid_connect.set_jwks_uri(provider_metadata.jwks_uri);
+    }
+
+    if !openid_connect.has_algorithms() {
+        // Algorithms which are not supported, such as 'none', are ignored.
+        let algorithms: Vec<Algorithm> = provider_metadata
+            .id_token_signing_alg_values_supported
+            .iter()
+            .filter_map(|algorithm| Algorithm::from_str(algorithm).ok())
+            .collect();
+
+        if !algorithms.is_empty() {
+            openid_connect.set_algorithms(algorithms);
+        }
+    }
+
+    openid_connect.add_issuer(provider_metadata.issuer);
+}
+
+fn try_build_http_client() -> Result<reqwest::Client, Error> {
+    match reqwest::Client::builder().timeout(HTTP_REQUEST_TIMEOUT).build() {
+        Ok(client) => Ok(client),
+        Err(error) => Err(Error::new(
+            ErrorKind::AutoConfigFailure,
+            format!("failed to build http client: {}", error),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const JWKS_PATH: &str = "/jwks";
+
+    /// Serves a status and body per path, given the stub's address, other paths
+    /// being not found. Returns the stub's address.
+    async fn serve(routes: impl FnOnce(&str) -> Vec<(&'static str, u16, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let routes = routes(address.as_str());
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+
+                let request = String::from_utf8_lossy(&request).to_string();
+                let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+                let (status, body) = match routes.iter().find(|route| route.0 == path) {
+                    Some((_, status, body)) => (*status, body.clone()),
+                    None => (404, String::new()),
+                };
+
+                let response = format!(
+                    "HTTP/1.1 {} STUB\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        address
+    }
+
+    fn openid_configuration(issuer: &str) -> String {
+        json!({
+            "issuer": issuer,
+            "jwks_uri": format!("{}{}", issuer, JWKS_PATH),
+            "id_token_signing_alg_values_supported": ["HS256", "none"],
+        })
+        .to_string()
+    }
+
+    fn jwks() -> String {
+        json!({"keys": [{"kty": "oct", "kid": "k1", "k": "c2VjcmV0"}]}).to_string()
+    }
+
+    fn discovered(issuer: &str) -> OpenIdConnectConfig {
+        OpenIdConnectConfig::from_discovery_issuer(issuer.to_string(), vec!["api".to_string()])
+    }
+
+    #[tokio::test]
+    async fn discovers_jwks_uri_issuer_and_algorithms() {
+        let issuer = serve(|address| {
+            vec![
+                (OPENID_CONFIGURATION_PATH, 200, openid_configuration(address)),
+                (JWKS_PATH, 200, jwks()),
+            ]
+        })
+        .await;
+
+        let config = try_generate_config(discovered(issuer.as_str())).await.unwrap();
+
+        assert_eq!(
+            config.open_id_connect().jwks_uri(),
+            format!("{}{}", issuer, JWKS_PATH)
+        );
+        assert_eq!(config.open_id_connect().issuers(), [issuer]);
+        assert_eq!(config.open_id_connect().algorithms(), [Algorithm::HS256]);
+        assert_eq!(config.jwks().keys.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_explicitly_configured_values() {
+        let issuer = serve(|address| {
+            vec![
+                (OPENID_CONFIGURATION_PATH, 200, openid_configuration(address)),
+                ("/configured", 200, jwks()),
+            ]
+        })
+        .await;
+
+        let mut openid_connect = discovered(issuer.as_str());
+        openid_connect.set_jwks_uri(format!("{}/configured", issuer));
+        openid_connect.set_algorithms(vec![Algorithm::HS512]);
+
+        let config = try_generate_config(openid_connect).await.unwrap();
+
+        assert_eq!(
+            config.open_id_connect().jwks_uri(),
+            format!("{}/configured", issuer)
+        );
+        assert_eq!(config.open_id_connect().algorithms(), [Algorithm::HS512]);
+    }
+
+    #[tokio::test]
+    async fn rejects_impersonating_issuer() {
+        let issuer = serve(|_| {
+            vec![(
+                OPENID_CONFIGURATION_PATH,
+                200,
+                openid_configuration("https://impersonated.example"),
+            )]
+        })
+        .await;
+
+        let error = try_generate_config(discovered(issuer.as_str()))
+            .await
+            .err()
+            .unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::AutoConfigFailure);
+        assert!(error.message.contains("does not match"));
+    }
+
+    #[tokio::test]
+    async fn reports_http_status_of_failed_discovery() {
+        let issuer = serve(|_| vec![(OPENID_CONFIGURATION_PATH, 500, String::new())]).await;
+
+        let error = try_generate_config(discovered(issuer.as_str()))
+            .await
+            .err()
+            .unwrap();
+
+        assert!(error.message.starts_with("failed to request openid configuration"));
+        assert!(error.message.contains("500"));
+    }
+
+    #[tokio::test]
+    async fn reports_http_status_of_failed_jwks_request() {
+        let issuer = serve(|address| {
+            vec![(OPENID_CONFIGURATION_PATH, 200, openid_configuration(address))]
+        })
+        .await;
+
+        let error = try_generate_config(discovered(issuer.as_str()))
+            .await
+            .err()
+            .unwrap();
+
+        assert!(error.message.starts_with("failed to request jwks"));
+        assert!(error.message.contains("404"));
+    }
+}
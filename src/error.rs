@@ -1,10 +1,13 @@
 use std::fmt;
 
+use crate::api::input::policy::PolicyViolation;
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ErrorKind {
     TokenDecodingFailure,
     MalformedToken,
     InvalidToken,
+    RevokedToken,
     PermissionNotFound,
     ApiRunnerAutoConfigFailure,
     ApiConnectionFailure,
@@ -17,12 +20,19 @@ pub enum ErrorKind {
     ApiNotFound,
     ApiRouterFailure,
     AmqpFailure,
+    ShutdownFailure,
+    ExpiredRequest,
+    DuplicateRequest,
+    RetryableFailure,
+    PermanentFailure,
+    TimedOutRequest,
 }
 
 #[derive(Debug, Clone)]
 pub struct Error {
     pub kind: ErrorKind,
     pub message: String,
+    violation: Option<PolicyViolation>,
 }
 
 impl Error {
@@ -30,12 +40,31 @@ impl Error {
         Error {
             kind,
             message: message.into(),
+            violation: None,
+        }
+    }
+
+    /// Error of a request rejected by a policy, whose policy and reason reach the reply
+    /// and the dead-letter headers as separate fields.
+    pub fn with_violation(
+        kind: ErrorKind,
+        message: impl Into<String>,
+        violation: PolicyViolation,
+    ) -> Error {
+        Error {
+            kind,
+            message: message.into(),
+            violation: Some(violation),
         }
     }
 
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    pub fn violation(&self) -> Option<&PolicyViolation> {
+        self.violation.as_ref()
+    }
 }
 
 impl fmt::Display for Error {
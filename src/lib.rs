@@ -1,3 +1,6 @@
 pub mod api;
 pub mod error;
 pub mod config;
+
+#[cfg(test)]
+pub(crate) mod test_support;
@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use amq_protocol::frame::{gen_frame, parse_frame, AMQPContentHeader, AMQPFrame, WriteContext};
+use amq_protocol::protocol::{basic, channel, confirm, connection, exchange, queue, AMQPClass};
+use amq_protocol::types::{ChannelId, FieldTable, LongString, ShortString};
+use cooplan_lapin_wrapper::config::amqp_connect_config::AmqpConnectConfig;
+use lapin::tcp::OwnedTLSConfig;
+use lapin::{BasicProperties, ConnectionProperties};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{Duration, Instant};
+
+use crate::api::connection_supervisor::ConnectionSupervisor;
+use crate::config::reconnection_config::ReconnectionConfig;
+
+const BASIC_CLASS_ID: u16 = 60;
+const FRAME_MAX: u32 = 131072;
+const WAIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Message published to the broker.
+#[derive(Debug, Clone)]
+pub(crate) struct Publication {
+    pub exchange: String,
+    pub routing_key: String,
+    pub properties: BasicProperties,
+    pub data: Vec<u8>,
+}
+
+/// Settlement of a delivery by its consumer.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Settlement {
+    Ack(u64),
+    Reject { delivery_tag: u64, requeue: bool },
+}
+
+struct Consumption {
+    queue: String,
+    consumer_tag: String,
+    channel_id: ChannelId,
+    frame_sender: mpsc::UnboundedSender<AMQPFrame>,
+}
+
+#[derive(Default)]
+struct BrokerState {
+    refusing: bool,
+    nacking: bool,
+    refused_connections: usize,
+    connections: usize,
+    consumptions: Vec<Consumption>,
+    publications: Vec<Publication>,
+    settlements: Vec<Settlement>,
+    declared_queues: usize,
+    queues: HashMap<String, queue::Declare>,
+    prefetch_counts: Vec<u16>,
+    delivery_tag: u64,
+}
+
+/// Broker speaking just enough amqp 0-9-1 for lapin: it accepts every declaration,
+/// binding and consumer, records the publications and drops its connections on demand.
+/// Mandatory publications to the default exchange are returned unless their queue
+/// has been declared, whereas other exchanges route everything.
+pub(crate) struct FakeBroker {
+    address: SocketAddr,
+    state: Arc<Mutex<BrokerState>>,
+    disconnect_sender: watch::Sender<u64>,
+}
+
+impl FakeBroker {
+    pub(crate) async fn start() -> FakeBroker {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(BrokerState::default()));
+        let (disconnect_sender, disconnect_receiver) = watch::channel(0);
+
+        let accepting_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(
+                    stream,
+                    accepting_state.clone(),
+                    disconnect_receiver.clone(),
+                ));
+            }
+        });
+
+        FakeBroker {
+            address,
+            state,
+            disconnect_sender,
+        }
+    }
+
+    pub(crate) fn connect_config(&self) -> AmqpConnectConfig {
+        AmqpConnectConfig::new(
+            format!("amqp://guest:guest@{}/%2f", self.address),
+            ConnectionProperties::default()
+                .with_executor(tokio_executor_trait::Tokio::current())
+                .with_reactor(tokio_reactor_trait::Tokio),
+            OwnedTLSConfig::default(),
+        )
+    }
+
+    pub(crate) fn connection_supervisor(&self) -> Arc<ConnectionSupervisor> {
+        Arc::new(
+            ConnectionSupervisor::try_new(
+                self.connect_config(),
+                ReconnectionConfig::new(10, 40),
+            )
+            .unwrap(),
+        )
+    }
+
+    /// Drops every open connection.
+    pub(crate) fn disconnect(&self) {
+        self.disconnect_sender.send_modify(|generation| *generation += 1);
+    }
+
+    /// Refused connections are closed right after being accepted.
+    pub(crate) fn set_refusing(&self, refusing: bool) {
+        self.state().refusing = refusing;
+    }
+
+    /// Publications on channels in confirm mode are nacked instead of being acked.
+    pub(crate) fn set_nacking(&self, nacking: bool) {
+        self.state().nacking = nacking;
+    }
+
+    pub(crate) fn connections(&self) -> usize {
+        self.state().connections
+    }
+
+    pub(crate) fn refused_connections(&self) -> usize {
+        self.state().refused_connections
+    }
+
+    /// Counts every consumer the queue ever had, including cancelled ones.
+    pub(crate) fn consumers_of(&self, queue: &str) -> usize {
+        self.state()
+            .consumptions
+            .iter()
+            .filter(|consumption| consumption.queue == queue)
+            .count()
+    }
+
+    /// Latest declaration of the queue.
+    pub(crate) fn declaration_of(&self, queue: &str) -> Option<queue::Declare> {
+        self.state().queues.get(queue).cloned()
+    }
+
+    /// Prefetch counts of every qos request, in the order they were received.
+    pub(crate) fn prefetch_counts(&self) -> Vec<u16> {
+        self.state().prefetch_counts.clone()
+    }
+
+    pub(crate) fn publications(&self) -> Vec<Publication> {
+        self.state().publications.clone()
+    }
+
+    pub(crate) fn settlements(&self) -> Vec<Settlement> {
+        self.state().settlements.clone()
+    }
+
+    /// Delivers the message to the latest consumer of the queue, returning whether
+    /// there was one still connected.
+    pub(crate) fn deliver(&self, queue: &str, properties: BasicProperties, data: &[u8]) -> bool {
+        let mut state = self.state();
+        state.delivery_tag += 1;
+        let delivery_tag = state.delivery_tag;
+
+        let consumption = match state
+            .consumptions
+            .iter()
+            .rev()
+            .find(|consumption| {
+                consumption.queue == queue && !consumption.frame_sender.is_closed()
+            })
+        {
+            Some(consumption) => consumption,
+            None => return false,
+        };
+
+        let channel_id = consumption.channel_id;
+        let frames = [
+            AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Deliver(basic::Deliver {
+                    consumer_tag: ShortString::from(consumption.consumer_tag.as_str()),
+                    delivery_tag,
+                    redelivered: false,
+                    exchange: ShortString::from(""),
+                    routing_key: ShortString::from(queue),
+                })),
+            ),
+            AMQPFrame::Header(
+                channel_id,
+                BASIC_CLASS_ID,
+                Box::new(AMQPContentHeader {
+                    class_id: BASIC_CLASS_ID,
+                    body_size: data.len() as u64,
+                    properties,
+                }),
+            ),
+            AMQPFrame::Body(channel_id, data.to_vec()),
+        ];
+
+        frames
+            .into_iter()
+            .all(|frame| consumption.frame_sender.send(frame).is_ok())
+    }
+
+    fn state(&self) -> MutexGuard<'_, BrokerState> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// Polls the condition until it holds, failing the test whenever it does not in time.
+pub(crate) async fn wait_until(description: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + WAIT_TIMEOUT;
+
+    while !condition() {
+        if Instant::now() > deadline {
+            panic!("timed out waiting until {}", description);
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+struct PendingPublication {
+    mandatory: bool,
+    exchange: String,
+    routing_key: String,
+    properties: BasicProperties,
+    body_size: u64,
+    data: Vec<u8>,
+}
+
+/// Server side of a single connection.
+struct BrokerConnection {
+    state: Arc<Mutex<BrokerState>>,
+    frame_sender: mpsc::UnboundedSender<AMQPFrame>,
+    pending_publications: HashMap<ChannelId, PendingPublication>,
+    confirmed_publications: HashMap<ChannelId, u64>,
+}
+
+async fn serve(
+    mut stream: TcpStream,
+    state: Arc<Mutex<BrokerState>>,
+    mut disconnect_receiver: watch::Receiver<u64>,
+) {
+    disconnect_receiver.borrow_and_update();
+
+    {
+        let mut state = state.lock().unwrap();
+
+        if state.refusing {
+            state.refused_connections += 1;
+            return;
+        }
+
+        state.connections += 1;
+    }
+
+    let (frame_sender, mut frame_receiver) = mpsc::unbounded_channel();
+    let mut connection = BrokerConnection {
+        state,
+        frame_sender,
+        pending_publications: HashMap::new(),
+        confirmed_publications: HashMap::new(),
+    };
+
+    let mut buffer = Vec::new();
+    let mut read_buffer = [0u8; 8192];
+
+    loop {
+        tokio::select! {
+            read = stream.read(&mut read_buffer) => {
+                match read {
+                    Ok(0) | Err(_) => return,
+                    Ok(read) => buffer.extend_from_slice(&read_buffer[..read]),
+                }
+
+                loop {
+                    let (remaining, frame) = match parse_frame(buffer.as_slice()) {
+                        Ok((remaining, frame)) => (remaining.len(), frame),
+                        Err(error) if error.is_incomplete() => break,
+                        Err(error) => panic!("fake broker failed to parse frame: {:?}", error),
+                    };
+
+                    buffer.drain(..buffer.len() - remaining);
+                    connection.handle(frame);
+                }
+            }
+            Some(frame) = frame_receiver.recv() => {
+                let frame = gen_frame(&frame)(WriteContext::from(Vec::new())).unwrap().write;
+
+                if stream.write_all(frame.as_slice()).await.is_err() {
+                    return;
+                }
+            }
+            _ = disconnect_receiver.changed() => return,
+        }
+    }
+}
+
+impl BrokerConnection {
+    fn handle(&mut self, frame: AMQPFrame) {
+        match frame {
+            AMQPFrame::ProtocolHeader(_) => self.send(
+                0,
+                AMQPClass::Connection(connection::AMQPMethod::Start(connection::Start {
+                    version_major: 0,
+                    version_minor: 9,
+                    server_properties: FieldTable::default(),
+                    mechanisms: LongString::from("PLAIN"),
+                    locales: LongString::from("en_US"),
+                })),
+            ),
+            AMQPFrame::Method(channel_id, method) => self.handle_method(channel_id, method),
+            AMQPFrame::Header(channel_id, _, header) => {
+                if let Some(publication) = self.pending_publications.get_mut(&channel_id) {
+                    publication.properties = header.properties;
+                    publication.body_size = header.body_size;
+                }
+
+                self.try_complete_publication(channel_id);
+            }
+            AMQPFrame::Body(channel_id, data) => {
+                if let Some(publication) = self.pending_publications.get_mut(&channel_id) {
+                    publication.data.extend(data);
+                }
+
+                self.try_complete_publication(channel_id);
+            }
+            AMQPFrame::Heartbeat(_) => (),
+        }
+    }
+
+    fn handle_method(&mut self, channel_id: ChannelId, method: AMQPClass) {
+        let reply = match method {
+            AMQPClass::Connection(connection::AMQPMethod::StartOk(_)) => {
+                AMQPClass::Connection(connection::AMQPMethod::Tune(connection::Tune {
+                    channel_max: 2047,
+                    frame_max: FRAME_MAX,
+                    heartbeat: 0,
+                }))
+            }
+            AMQPClass::Connection(connection::AMQPMethod::Open(_)) => {
+                AMQPClass::Connection(connection::AMQPMethod::OpenOk(connection::OpenOk {}))
+            }
+            AMQPClass::Connection(connection::AMQPMethod::Close(_)) => {
+                AMQPClass::Connection(connection::AMQPMethod::CloseOk(connection::CloseOk {}))
+            }
+            AMQPClass::Channel(channel::AMQPMethod::Open(_)) => {
+                AMQPClass::Channel(channel::AMQPMethod::OpenOk(channel::OpenOk {}))
+            }
+            AMQPClass::Channel(channel::AMQPMethod::Close(_)) => {
+                AMQPClass::Channel(channel::AMQPMethod::CloseOk(channel::CloseOk {}))
+            }
+            AMQPClass::Exchange(exchange::AMQPMethod::Declare(declare)) if !declare.nowait => {
+                AMQPClass::Exchange(exchange::AMQPMethod::DeclareOk(exchange::DeclareOk {}))
+            }
+            AMQPClass::Queue(queue::AMQPMethod::Declare(declare)) if !declare.nowait => {
+                let mut state = self.state.lock().unwrap();
+                state.declared_queues += 1;
+
+                let queue_name = match declare.queue.as_str() {
+                    "" => format!("amq.gen-{}", state.declared_queues),
+                    queue_name => queue_name.to_string(),
+                };
+                state.queues.insert(queue_name.clone(), declare.clone());
+
+                AMQPClass::Queue(queue::AMQPMethod::DeclareOk(queue::DeclareOk {
+                    queue: ShortString::from(queue_name),
+                    message_count: 0,
+                    consumer_count: 0,
+                }))
+            }
+            AMQPClass::Queue(queue::AMQPMethod::Bind(bind)) if !bind.nowait => {
+                AMQPClass::Queue(queue::AMQPMethod::BindOk(queue::BindOk {}))
+            }
+            AMQPClass::Basic(basic::AMQPMethod::Qos(qos)) => {
+                self.state.lock().unwrap().prefetch_counts.push(qos.prefetch_count);
+
+                AMQPClass::Basic(basic::AMQPMethod::QosOk(basic::QosOk {}))
+            }
+            AMQPClass::Basic(basic::AMQPMethod::Consume(consume)) => {
+                let consumer_tag = consume.consumer_tag.to_string();
+
+                self.state.lock().unwrap().consumptions.push(Consumption {
+                    queue: consume.queue.to_string(),
+                    consumer_tag: consumer_tag.clone(),
+                    channel_id,
+                    frame_sender: self.frame_sender.clone(),
+                });
+
+                if consume.nowait {
+                    return;
+                }
+
+                AMQPClass::Basic(basic::AMQPMethod::ConsumeOk(basic::ConsumeOk {
+                    consumer_tag: ShortString::from(consumer_tag),
+                }))
+            }
+            AMQPClass::Basic(basic::AMQPMethod::Cancel(cancel)) if !cancel.nowait => {
+                AMQPClass::Basic(basic::AMQPMethod::CancelOk(basic::CancelOk {
+                    consumer_tag: cancel.consumer_tag,
+                }))
+            }
+            AMQPClass::Basic(basic::AMQPMethod::Publish(publish)) => {
+                self.pending_publications.insert(
+                    channel_id,
+                    PendingPublication {
+                        mandatory: publish.mandatory,
+                        exchange: publish.exchange.to_string(),
+                        routing_key: publish.routing_key.to_string(),
+                        properties: BasicProperties::default(),
+                        body_size: u64::MAX,
+                        data: Vec::new(),
+                    },
+                );
+
+                return;
+            }
+            AMQPClass::Basic(basic::AMQPMethod::Ack(ack)) => {
+                self.settle(Settlement::Ack(ack.delivery_tag));
+                return;
+            }
+            AMQPClass::Basic(basic::AMQPMethod::Reject(reject)) => {
+                self.settle(Settlement::Reject {
+                    delivery_tag: reject.delivery_tag,
+                    requeue: reject.requeue,
+                });
+                return;
+            }
+            AMQPClass::Confirm(confirm::AMQPMethod::Select(select)) => {
+                self.confirmed_publications.insert(channel_id, 0);
+
+                if select.nowait {
+                    return;
+                }
+
+                AMQPClass::Confirm(confirm::AMQPMethod::SelectOk(confirm::SelectOk {}))
+            }
+            _ => return,
+        };
+
+        self.send(channel_id, reply);
+    }
+
+    fn try_complete_publication(&mut self, channel_id: ChannelId) {
+        match self.pending_publications.get(&channel_id) {
+            Some(publication) if publication.data.len() as u64 >= publication.body_size => (),
+            _ => return,
+        }
+
+        let publication = match self.pending_publications.remove(&channel_id) {
+            Some(publication) => publication,
+            None => return,
+        };
+
+        let (nacking, unroutable) = {
+            let mut state = self.state.lock().unwrap();
+            let unroutable = publication.mandatory
+                && publication.exchange.is_empty()
+                && !state.queues.contains_key(&publication.routing_key);
+            state.publications.push(Publication {
+                exchange: publication.exchange.clone(),
+                routing_key: publication.routing_key.clone(),
+                properties: publication.properties.clone(),
+                data: publication.data.clone(),
+            });
+
+            (state.nacking, unroutable)
+        };
+
+        if unroutable {
+            self.return_publication(channel_id, publication);
+        }
+
+        let delivery_tag = match self.confirmed_publications.get_mut(&channel_id) {
+            Some(delivery_tag) => {
+                *delivery_tag += 1;
+                *delivery_tag
+            }
+            None => return,
+        };
+
+        let confirmation = match nacking {
+            true => basic::AMQPMethod::Nack(basic::Nack {
+                delivery_tag,
+                multiple: false,
+                requeue: false,
+            }),
+            false => basic::AMQPMethod::Ack(basic::Ack {
+                delivery_tag,
+                multiple: false,
+            }),
+        };
+
+        self.send(channel_id, AMQPClass::Basic(confirmation));
+    }
+
+    fn return_publication(&self, channel_id: ChannelId, publication: PendingPublication) {
+        let frames = [
+            AMQPFrame::Method(
+                channel_id,
+                AMQPClass::Basic(basic::AMQPMethod::Return(basic::Return {
+                    reply_code: 312,
+                    reply_text: ShortString::from("NO_ROUTE"),
+                    exchange: ShortString::from(publication.exchange),
+                    routing_key: ShortString::from(publication.routing_key),
+                })),
+            ),
+            AMQPFrame::Header(
+                channel_id,
+                BASIC_CLASS_ID,
+                Box::new(AMQPContentHeader {
+                    class_id: BASIC_CLASS_ID,
+                    body_size: publication.data.len() as u64,
+                    properties: publication.properties,
+                }),
+            ),
+            AMQPFrame::Body(channel_id, publication.data),
+        ];
+
+        for frame in frames {
+            let _ = self.frame_sender.send(frame);
+        }
+    }
+
+    fn settle(&self, settlement: Settlement) {
+        self.state.lock().unwrap().settlements.push(settlement);
+    }
+
+    fn send(&self, channel_id: ChannelId, method: AMQPClass) {
+        let _ = self.frame_sender.send(AMQPFrame::Method(channel_id, method));
+    }
+}
@@ -0,0 +1,148 @@
+pub(crate) mod fake_broker;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use cooplan_lapin_wrapper::config::amqp_output_api::AmqpOutputApi;
+use cooplan_lapin_wrapper::config::amqp_queue_consumer::AmqpQueueConsumer;
+use cooplan_lapin_wrapper::config::api::Api;
+use cooplan_state_tracker::state_tracker_client;
+use cooplan_state_tracker::state_tracker_client::StateTrackerClient;
+use cooplan_state_tracker::state_tracking_config::StateTrackingConfig;
+use lapin::options::{
+    BasicAckOptions, BasicConsumeOptions, BasicPublishOptions, BasicQosOptions,
+    BasicRejectOptions, QueueDeclareOptions,
+};
+use lapin::types::FieldTable;
+use lapin::BasicProperties;
+use serde_json::{json, Value};
+
+use crate::api::input::input_element;
+use crate::api::input::input_element::{InputElement, RequestHandler};
+
+static STATE_TRACKER_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// File within the temporary directory, removed once dropped.
+pub(crate) struct TemporaryFile {
+    path: String,
+}
+
+impl TemporaryFile {
+    pub(crate) fn new(content: &str) -> TemporaryFile {
+        let path = std::env::temp_dir()
+            .join(format!("cooplan-test-{}", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .to_string();
+        let file = TemporaryFile { path };
+        file.write(content);
+
+        file
+    }
+
+    pub(crate) fn path(&self) -> &str {
+        self.path.as_str()
+    }
+
+    pub(crate) fn write(&self, content: &str) {
+        std::fs::write(self.path.as_str(), content).unwrap();
+    }
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.path.as_str());
+    }
+}
+
+/// State tracker client whose states are sent to a socket nobody listens to.
+pub(crate) async fn state_tracker_client() -> StateTrackerClient {
+    let count = STATE_TRACKER_COUNT.fetch_add(1, Ordering::Relaxed);
+    let directory = std::env::temp_dir();
+    let sender_path = directory.join(format!("state-tracker-{}-{}", std::process::id(), count));
+
+    let _ = std::fs::remove_file(&sender_path);
+
+    state_tracker_client::build(
+        StateTrackingConfig {
+            state_output_sender_path: sender_path.to_string_lossy().to_string(),
+            state_output_receiver_path: directory
+                .join("state-tracker-receiver")
+                .to_string_lossy()
+                .to_string(),
+            state_sender_interval_in_seconds: 60,
+        },
+        16,
+    )
+    .await
+}
+
+/// Consumer of the queue with the default options of lapin.
+pub(crate) fn queue_consumer(queue_name: &str, prefetch_count: u16) -> AmqpQueueConsumer {
+    serde_json::from_value(json!({
+        "queue": queue(queue_name),
+        "qos": {
+            "prefetch_count": prefetch_count,
+            "options": BasicQosOptions::default(),
+        },
+        "consume": {
+            "options": BasicConsumeOptions::default(),
+            "arguments": FieldTable::default(),
+        },
+        "acknowledge": BasicAckOptions::default(),
+        "reject": BasicRejectOptions::default(),
+    }))
+    .unwrap()
+}
+
+/// Input element consuming the queue named after it.
+pub(crate) fn input_element<LogicRequestType>(
+    name: &str,
+    actions: &'static [&'static str],
+    request_handler: RequestHandler<LogicRequestType>,
+) -> InputElement<LogicRequestType> {
+    limited_input_element(name, actions, request_handler, 8)
+}
+
+/// Input element consuming the queue named after it, handling at most
+/// 'max_concurrent_requests' requests at once.
+pub(crate) fn limited_input_element<LogicRequestType>(
+    name: &str,
+    actions: &'static [&'static str],
+    request_handler: RequestHandler<LogicRequestType>,
+    max_concurrent_requests: u16,
+) -> InputElement<LogicRequestType> {
+    let api: Api = serde_json::from_value(json!({
+        "input": [{
+            "id": name,
+            "queue_consumer": queue_consumer(name, 0),
+            "max_concurrent_requests": max_concurrent_requests,
+        }],
+        "output": [],
+    }))
+    .unwrap();
+
+    input_element::extract_input(&api, name, request_handler, actions).unwrap()
+}
+
+/// Output publishing to the queue through the default exchange.
+pub(crate) fn output_config(queue_name: &str) -> AmqpOutputApi {
+    serde_json::from_value(json!({
+        "id": queue_name,
+        "queue": queue(queue_name),
+        "publish": {
+            "exchange": "",
+            "options": BasicPublishOptions::default(),
+            "properties": BasicProperties::default(),
+        },
+    }))
+    .unwrap()
+}
+
+fn queue(queue_name: &str) -> Value {
+    json!({
+        "name": queue_name,
+        "declare": {
+            "options": QueueDeclareOptions::default(),
+            "arguments": FieldTable::default(),
+        },
+    })
+}